@@ -0,0 +1,325 @@
+//! Runtime configuration.
+//!
+//! The configuration is read, in increasing order of precedence, from:
+//!
+//!  - the built-in defaults,
+//!  - the configuration file (`fdtshim.conf` next to the image, or `--config=`),
+//!  - the load options.
+//!
+//! Toggles can also be enabled with fdtshim's EFI variables, see [`crate::efi`]:
+//!
+//!  - `FdtshimExplain`: as `--explain`, when its first byte is non-zero.
+//!  - `FdtshimDryRun`: as `--dry-run`, when its first byte is non-zero.
+//!
+//! As well as the `FdtshimLogLevel` EFI variable, as `--log-level`, taking precedence
+//! over the configuration file.
+//!
+//! The configuration file is made of `key value` lines, `#` starts a comment.
+//!
+//! ```text
+//! # Searched in order
+//! prefix \dtb-6.9.3
+//! prefix \EFI\dtbs
+//! mapping mapping.dtb
+//! # Searched in order, see [`crate::volumes`]
+//! volume xbootldr
+//! volume self
+//! # Seconds to wait for a startup hotkey, see [`crate::hotkey`]
+//! timeout 3
+//! # When the menu is shown, see [`crate::menu`]
+//! menu on-failure
+//! # Seconds before the menu selects the default
+//! menu-timeout 10
+//! # See [`crate::logger`]
+//! log-level debug
+//! log console
+//! log serial
+//! log-file \EFI\dtbs\fdtshim.log
+//! # On a dtb size or SHA-256 mismatch, see [`crate::integrity`]
+//! integrity-policy firmware
+//! ```
+
+use crate::efi::*;
+use crate::global::Global;
+use crate::integrity::IntegrityPolicy;
+use crate::logger::*;
+use crate::menu::MenuPolicy;
+use crate::options::LoadOptions;
+use crate::utils::*;
+use crate::volumes::VolumeSelector;
+use crate::DEFAULT_MAPPING;
+use crate::DEFAULT_PREFIX;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::OnceCell;
+use log::debug;
+use log::warn;
+use log::LevelFilter;
+use uefi::cstr16;
+use uefi::fs::PathBuf;
+use uefi::prelude::*;
+use uefi::CStr16;
+use uefi::CString16;
+
+/// Default seconds before the menu selects the default.
+///
+/// Also used instead of `0` for menus shown without the hotkey, so as not to stall unattended
+/// boots.
+pub const DEFAULT_MENU_TIMEOUT: usize = 10;
+
+/// Name of the configuration file, looked up in the image's directory.
+pub const CONFIG_FILE: &str = r"fdtshim.conf";
+
+pub struct Config {
+    /// Prefixes searched for the mapping and dtb files, in priority order.
+    pub prefixes: Vec<String>,
+    /// Mapping file path, relative to the prefixes unless absolute.
+    pub mapping: String,
+    /// Volumes searched for files, in priority order.
+    pub volumes: Vec<VolumeSelector>,
+    /// Whether to report how the device was matched, see [`crate::matching`].
+    pub explain: bool,
+    /// Whether to match and validate the dtb without installing it.
+    pub dry_run: bool,
+    /// Seconds to wait for a startup hotkey.
+    pub timeout: usize,
+    /// When the menu is shown.
+    pub menu: MenuPolicy,
+    /// Seconds before the menu selects the default.
+    ///
+    /// `0` waits indefinitely, only when the menu was opened with the hotkey.
+    pub menu_timeout: usize,
+    /// Log level, when configured.
+    pub log_level: Option<LevelFilter>,
+    /// Log destinations.
+    pub log: Vec<LogDestination>,
+    /// Log file, when logging to a file.
+    pub log_file: String,
+    /// What to do when the dtb fails the integrity verification.
+    pub integrity_policy: IntegrityPolicy,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prefixes: vec![DEFAULT_PREFIX.to_string()],
+            mapping: DEFAULT_MAPPING.to_string(),
+            volumes: vec![VolumeSelector::Image],
+            explain: false,
+            dry_run: false,
+            timeout: 0,
+            menu: MenuPolicy::default(),
+            menu_timeout: DEFAULT_MENU_TIMEOUT,
+            log_level: None,
+            log: vec![LogDestination::Console],
+            log_file: DEFAULT_LOG_FILE.to_string(),
+            integrity_policy: IntegrityPolicy::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Applies the `key value` lines of a configuration file.
+    fn parse(&mut self, contents: &str) {
+        let mut prefixes = Vec::new();
+        let mut volumes = Vec::new();
+        let mut log = Vec::new();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            match key {
+                "prefix" => prefixes.extend(parse_path(value)),
+                "mapping" => self.mapping = parse_path(value).unwrap_or(self.mapping.clone()),
+                "volume" => volumes.extend(parse_volume_selector(value)),
+                "timeout" => self.timeout = parse_timeout(value).unwrap_or(self.timeout),
+                "menu" => self.menu = parse_menu_policy(value).unwrap_or(self.menu),
+                "menu-timeout" => {
+                    self.menu_timeout = parse_timeout(value).unwrap_or(self.menu_timeout)
+                }
+                "log-level" => self.log_level = parse_log_level(value).or(self.log_level),
+                "log" => log.extend(parse_log_destination(value)),
+                "log-file" => self.log_file = parse_path(value).unwrap_or(self.log_file.clone()),
+                "integrity-policy" => {
+                    self.integrity_policy =
+                        parse_integrity_policy(value).unwrap_or(self.integrity_policy)
+                }
+                _ => warn!("Unknown configuration key {:?}", key),
+            }
+        }
+        if !prefixes.is_empty() {
+            self.prefixes = prefixes;
+        }
+        if !volumes.is_empty() {
+            self.volumes = volumes;
+        }
+        if !log.is_empty() {
+            self.log = log;
+        }
+    }
+
+    /// Builds the configuration from all sources.
+    pub fn load(st: &SystemTable<Boot>, load_options: &LoadOptions) -> Self {
+        let bs = st.boot_services();
+        let mut config = Self::default();
+
+        let config_path = match load_options.option("config") {
+            Some(path) => CString16::try_from(path).ok(),
+            None => image_directory(bs).map(|directory| {
+                let mut path = PathBuf::from(directory);
+                path.push(PathBuf::from(CString16::try_from(CONFIG_FILE).unwrap()));
+                path.to_cstr16().into()
+            }),
+        };
+        if let Some(config_path) = config_path {
+            debug!("Reading {:?}", config_path.to_string());
+            // Not configured yet; only the default volumes are searched.
+            match read_file_in(bs, &config.volumes, config_path) {
+                Ok(contents) => match core::str::from_utf8(&contents) {
+                    Ok(contents) => config.parse(contents),
+                    Err(_) => warn!("Configuration file is not valid UTF-8; ignoring."),
+                },
+                Err(_) => debug!("    (No configuration file.)"),
+            }
+        }
+
+        let prefixes: Vec<String> = load_options
+            .option_values("prefix")
+            .filter_map(parse_path)
+            .collect();
+        if !prefixes.is_empty() {
+            config.prefixes = prefixes;
+        }
+        if let Some(mapping) = load_options.option("mapping").and_then(parse_path) {
+            config.mapping = mapping;
+        }
+        let volumes: Vec<VolumeSelector> = load_options
+            .option_values("volume")
+            .filter_map(parse_volume_selector)
+            .collect();
+        if !volumes.is_empty() {
+            config.volumes = volumes;
+        }
+        if let Some(timeout) = load_options.option("timeout") {
+            config.timeout = parse_timeout(timeout).unwrap_or(config.timeout);
+        }
+        if let Some(menu) = load_options.option("menu") {
+            config.menu = parse_menu_policy(menu).unwrap_or(config.menu);
+        }
+        if let Some(timeout) = load_options.option("menu-timeout") {
+            config.menu_timeout = parse_timeout(timeout).unwrap_or(config.menu_timeout);
+        }
+        if let Some(level) = get_fdtshim_string_variable(st, cstr16!("FdtshimLogLevel")) {
+            config.log_level = parse_log_level(&level).or(config.log_level);
+        }
+        if let Some(level) = load_options.option("log-level") {
+            config.log_level = parse_log_level(level).or(config.log_level);
+        }
+        let log: Vec<LogDestination> = load_options
+            .option_values("log")
+            .filter_map(parse_log_destination)
+            .collect();
+        if !log.is_empty() {
+            config.log = log;
+        }
+        if let Some(log_file) = load_options.option("log-file").and_then(parse_path) {
+            config.log_file = log_file;
+        }
+        if let Some(policy) = load_options.option("integrity-policy") {
+            config.integrity_policy =
+                parse_integrity_policy(policy).unwrap_or(config.integrity_policy);
+        }
+        config.explain = load_options.option("explain").is_some()
+            || toggle_variable(st, cstr16!("FdtshimExplain"));
+        config.dry_run = load_options.option("dry-run").is_some()
+            || toggle_variable(st, cstr16!("FdtshimDryRun"));
+
+        debug!("Prefixes: {:?}", config.prefixes);
+        debug!("Mapping: {:?}", config.mapping);
+        debug!("Volumes: {:?}", config.volumes);
+        debug!("Explain: {:?}", config.explain);
+        debug!("Dry run: {:?}", config.dry_run);
+        debug!("Timeout: {:?}", config.timeout);
+        debug!("Menu: {:?}", config.menu);
+        debug!("Menu timeout: {:?}", config.menu_timeout);
+        debug!("Log level: {:?}", config.log_level);
+        debug!("Log: {:?}", config.log);
+        debug!("Log file: {:?}", config.log_file);
+        debug!("Integrity policy: {:?}", config.integrity_policy);
+
+        config
+    }
+}
+
+/// Whether one of fdtshim's toggle EFI variables is set, to a non-zero first byte.
+fn toggle_variable(st: &SystemTable<Boot>, name: &CStr16) -> bool {
+    get_fdtshim_variable(st, name).is_some_and(|value| value.first().is_some_and(|b| *b != 0))
+}
+
+/// Parses a configuration value, warning when invalid.
+fn parse_or_warn<T>(name: &str, value: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let parsed = parse(value);
+    if parsed.is_none() {
+        warn!("Invalid {} {:?}", name, value);
+    }
+    parsed
+}
+
+/// Paths are used as UCS-2 by UEFI.
+fn parse_path(value: &str) -> Option<String> {
+    parse_or_warn("path", value, |value| {
+        is_valid_path(value).then(|| value.to_string())
+    })
+}
+
+fn parse_timeout(value: &str) -> Option<usize> {
+    parse_or_warn("timeout", value, |value| value.parse().ok())
+}
+
+fn parse_log_level(value: &str) -> Option<LevelFilter> {
+    parse_or_warn("log level", value, parse_level)
+}
+
+fn parse_log_destination(value: &str) -> Option<LogDestination> {
+    parse_or_warn("log destination", value, LogDestination::parse)
+}
+
+fn parse_integrity_policy(value: &str) -> Option<IntegrityPolicy> {
+    parse_or_warn("integrity policy", value, IntegrityPolicy::parse)
+}
+
+fn parse_menu_policy(value: &str) -> Option<MenuPolicy> {
+    parse_or_warn("menu policy", value, MenuPolicy::parse)
+}
+
+fn parse_volume_selector(value: &str) -> Option<VolumeSelector> {
+    parse_or_warn("volume selector", value, VolumeSelector::parse)
+}
+
+static CONFIG: Global<OnceCell<Config>> = Global::new(OnceCell::new());
+
+/// Sets the global runtime configuration.
+///
+/// # Panics
+/// When the configuration is already set.
+pub fn set_config(config: Config) {
+    if CONFIG.get().set(config).is_err() {
+        panic!("The configuration is already set");
+    }
+}
+
+/// Gets the global runtime configuration.
+///
+/// # Panics
+/// When the configuration is not set yet, see [`set_config`].
+pub fn config() -> &'static Config {
+    CONFIG
+        .get()
+        .get()
+        .expect("The configuration is not set yet")
+}
@@ -0,0 +1,550 @@
+//! Unflattened device trees, and overlay (`.dtbo`) application.
+//!
+//! `flat_device_tree` only provides a read-only view of an FDT, so the base
+//! DTB is unflattened into a [`DeviceTree`], overlays are merged into it, and
+//! the result is flattened back into a new DTB blob.
+//!
+//! The semantics follow libfdt's `fdt_overlay_apply`:
+//!
+//!  - https://github.com/dgibson/dtc/blob/main/libfdt/fdt_overlay.c
+//!
+
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use flat_device_tree::Fdt;
+use log::debug;
+use log::warn;
+
+#[cfg(test)]
+mod tests;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// Size of the FDT header for version 17.
+const FDT_HEADER_SIZE: usize = 40;
+
+#[derive(Clone)]
+pub struct Property {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct Node {
+    pub name: String,
+    pub props: Vec<Property>,
+    pub children: Vec<Node>,
+}
+
+/// An unflattened, mutable, device tree.
+#[derive(Clone)]
+pub struct DeviceTree {
+    boot_cpuid_phys: u32,
+    reservations: Vec<(u64, u64)>,
+    pub root: Node,
+}
+
+/// Why a device tree could not be unflattened, or an overlay applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceTreeError {
+    /// The FDT could not be parsed, or has no root node.
+    BadFdt,
+    /// A `__local_fixups__` entry references a missing node, property or offset.
+    BadLocalFixup,
+    /// An overlay phandle does not fit in a cell once offset past the base phandles.
+    PhandleOverflow,
+    /// A `__fixups__` label is missing from the base `__symbols__`, or its node has no phandle.
+    SymbolNotFound,
+    /// A `__fixups__` location is malformed, or missing from the overlay.
+    BadFixup,
+    /// A fragment has neither `target` nor `target-path`.
+    MissingTarget,
+    /// A fragment target is missing from the base tree.
+    TargetNotFound,
+}
+
+impl fmt::Display for DeviceTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadFdt => write!(f, "invalid FDT"),
+            Self::BadLocalFixup => write!(f, "invalid __local_fixups__"),
+            Self::PhandleOverflow => write!(f, "phandle overflow"),
+            Self::SymbolNotFound => write!(f, "symbol not found"),
+            Self::BadFixup => write!(f, "invalid __fixups__"),
+            Self::MissingTarget => write!(f, "fragment without target"),
+            Self::TargetNotFound => write!(f, "fragment target not found"),
+        }
+    }
+}
+
+pub type DeviceTreeResult<T> = core::result::Result<T, DeviceTreeError>;
+
+fn be32(value: &[u8], offset: usize) -> Option<u32> {
+    let bytes = value.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn error<T>(err: DeviceTreeError) -> DeviceTreeResult<T> {
+    Err(err)
+}
+
+/// Offsets an overlay phandle by `delta`, failing when it does not fit in a cell.
+fn offset_phandle(phandle: u32, delta: u32) -> DeviceTreeResult<u32> {
+    match phandle.checked_add(delta) {
+        Some(phandle) => Ok(phandle),
+        None => {
+            warn!("    Phandle {phandle:#x} overflows once offset by {delta:#x}");
+            error(DeviceTreeError::PhandleOverflow)
+        }
+    }
+}
+
+/// Whether `name` is matched by the path component `looking_for`.
+///
+/// As with libfdt, the unit address may be omitted from the path component.
+fn name_matches(name: &str, looking_for: &str) -> bool {
+    name == looking_for
+        || (!looking_for.contains('@') && name.split('@').next() == Some(looking_for))
+}
+
+impl Node {
+    fn from_fdt_node(node: flat_device_tree::node::FdtNode) -> Self {
+        Self {
+            name: node.name.to_string(),
+            props: node
+                .properties()
+                .map(|prop| Property {
+                    name: prop.name.to_string(),
+                    value: prop.value.to_vec(),
+                })
+                .collect(),
+            children: node.children().map(Self::from_fdt_node).collect(),
+        }
+    }
+
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            props: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.props
+            .iter()
+            .find(|prop| prop.name == name)
+            .map(|prop| prop.value.as_slice())
+    }
+
+    pub fn property_mut(&mut self, name: &str) -> Option<&mut Vec<u8>> {
+        self.props
+            .iter_mut()
+            .find(|prop| prop.name == name)
+            .map(|prop| &mut prop.value)
+    }
+
+    pub fn property_str(&self, name: &str) -> Option<&str> {
+        core::str::from_utf8(self.property(name)?)
+            .map(|s| s.trim_end_matches('\0'))
+            .ok()
+    }
+
+    /// Adds or replaces a property.
+    pub fn set_property(&mut self, name: &str, value: &[u8]) {
+        if let Some(existing) = self.property_mut(name) {
+            *existing = value.to_vec();
+        } else {
+            self.props.push(Property {
+                name: name.to_string(),
+                value: value.to_vec(),
+            });
+        }
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children
+            .iter()
+            .find(|child| name_matches(&child.name, name))
+    }
+
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut Node> {
+        self.children
+            .iter_mut()
+            .find(|child| name_matches(&child.name, name))
+    }
+
+    /// Gets the named child, creating it when missing.
+    fn child_or_insert(&mut self, name: &str) -> &mut Node {
+        if let Some(index) = self.children.iter().position(|child| child.name == name) {
+            &mut self.children[index]
+        } else {
+            self.children.push(Node::new(name));
+            self.children.last_mut().unwrap()
+        }
+    }
+
+    pub fn find(&self, path: &str) -> Option<&Node> {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |node, part| node.child(part))
+    }
+
+    pub fn find_mut(&mut self, path: &str) -> Option<&mut Node> {
+        path.split('/')
+            .filter(|part| !part.is_empty())
+            .try_fold(self, |node, part| node.child_mut(part))
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|value| be32(value, 0))
+    }
+
+    /// Finds the path (relative to this node) of the node with the given phandle.
+    fn path_of_phandle(&self, phandle: u32) -> Option<String> {
+        if self.phandle() == Some(phandle) {
+            return Some(String::new());
+        }
+        self.children.iter().find_map(|child| {
+            child
+                .path_of_phandle(phandle)
+                .map(|rest| format!("/{}{}", child.name, rest))
+        })
+    }
+
+    fn max_phandle(&self) -> u32 {
+        self.children
+            .iter()
+            .map(Node::max_phandle)
+            .fold(self.phandle().unwrap_or(0), u32::max)
+    }
+
+    /// Offsets all phandles of this subtree by `delta`.
+    fn adjust_phandles(&mut self, delta: u32) -> DeviceTreeResult<()> {
+        for prop in self.props.iter_mut() {
+            if prop.name == "phandle" || prop.name == "linux,phandle" {
+                if let Some(phandle) = be32(&prop.value, 0) {
+                    prop.value = offset_phandle(phandle, delta)?.to_be_bytes().to_vec();
+                }
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.adjust_phandles(delta)?;
+        }
+        Ok(())
+    }
+
+    /// Offsets the phandle references listed by the `__local_fixups__` mirror node.
+    fn adjust_local_references(&mut self, fixups: &Node, delta: u32) -> DeviceTreeResult<()> {
+        for fixup in fixups.props.iter() {
+            let Some(value) = self.property_mut(&fixup.name) else {
+                warn!(
+                    "    __local_fixups__ references missing property {:?}",
+                    fixup.name
+                );
+                return error(DeviceTreeError::BadLocalFixup);
+            };
+            for offset in (0..fixup.value.len()).step_by(4) {
+                let offset = be32(&fixup.value, offset).unwrap_or(u32::MAX) as usize;
+                let Some(phandle) = be32(value, offset) else {
+                    warn!(
+                        "    __local_fixups__ offset out of bounds for {:?}",
+                        fixup.name
+                    );
+                    return error(DeviceTreeError::BadLocalFixup);
+                };
+                let phandle = offset_phandle(phandle, delta)?;
+                // In bounds, as read by `be32` above.
+                value[offset..offset + 4].copy_from_slice(&phandle.to_be_bytes());
+            }
+        }
+        for fixups_child in fixups.children.iter() {
+            let Some(child) = self
+                .children
+                .iter_mut()
+                .find(|child| child.name == fixups_child.name)
+            else {
+                warn!(
+                    "    __local_fixups__ references missing node {:?}",
+                    fixups_child.name
+                );
+                return error(DeviceTreeError::BadLocalFixup);
+            };
+            child.adjust_local_references(fixups_child, delta)?;
+        }
+        Ok(())
+    }
+
+    /// Merges an `__overlay__` node into this node.
+    fn merge(&mut self, overlay: &Node) {
+        for prop in overlay.props.iter() {
+            self.set_property(&prop.name, &prop.value);
+        }
+        for child in overlay.children.iter() {
+            self.child_or_insert(&child.name).merge(child);
+        }
+    }
+
+    fn flatten(&self, structs: &mut Vec<u8>, strings: &mut Vec<u8>) {
+        structs.extend_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+        structs.extend_from_slice(self.name.as_bytes());
+        structs.push(0);
+        pad4(structs);
+        for prop in self.props.iter() {
+            structs.extend_from_slice(&FDT_PROP.to_be_bytes());
+            structs.extend_from_slice(&(prop.value.len() as u32).to_be_bytes());
+            structs.extend_from_slice(&string_offset(strings, &prop.name).to_be_bytes());
+            structs.extend_from_slice(&prop.value);
+            pad4(structs);
+        }
+        for child in self.children.iter() {
+            child.flatten(structs, strings);
+        }
+        structs.extend_from_slice(&FDT_END_NODE.to_be_bytes());
+    }
+}
+
+fn pad4(buf: &mut Vec<u8>) {
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+/// Gets the offset of `name` in the strings block, adding it if needed.
+fn string_offset(strings: &mut Vec<u8>, name: &str) -> u32 {
+    let mut offset = 0;
+    for s in strings.split(|b| *b == 0) {
+        if s == name.as_bytes() && offset < strings.len() {
+            return offset as u32;
+        }
+        offset += s.len() + 1;
+    }
+    let offset = strings.len();
+    strings.extend_from_slice(name.as_bytes());
+    strings.push(0);
+    offset as u32
+}
+
+impl DeviceTree {
+    pub fn from_fdt(fdt: &Fdt) -> DeviceTreeResult<Self> {
+        let Some(root) = fdt.find_node("/") else {
+            return error(DeviceTreeError::BadFdt);
+        };
+        let mut root = Node::from_fdt_node(root);
+        root.name = String::new();
+
+        Ok(Self {
+            boot_cpuid_phys: be32(fdt.raw_data(), 28).unwrap_or(0),
+            reservations: fdt
+                .memory_reservations()
+                .map(|reservation| {
+                    (
+                        reservation.address() as usize as u64,
+                        reservation.size() as u64,
+                    )
+                })
+                .collect(),
+            root,
+        })
+    }
+
+    pub fn from_bytes(data: &[u8]) -> DeviceTreeResult<Self> {
+        match Fdt::new(data) {
+            Ok(fdt) => Self::from_fdt(&fdt),
+            Err(err) => {
+                warn!("    Could not parse FDT: {err:?}");
+                error(DeviceTreeError::BadFdt)
+            }
+        }
+    }
+
+    /// Flattens the tree back into a DTB blob.
+    pub fn to_dtb(&self) -> Vec<u8> {
+        let mut structs = Vec::new();
+        let mut strings = Vec::new();
+        self.root.flatten(&mut structs, &mut strings);
+        structs.extend_from_slice(&FDT_END.to_be_bytes());
+
+        let off_mem_rsvmap = FDT_HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + (self.reservations.len() + 1) * 16;
+        let off_dt_strings = off_dt_struct + structs.len();
+        let totalsize = off_dt_strings + strings.len();
+
+        let mut dtb = Vec::with_capacity(totalsize);
+        for field in [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17, // version
+            16, // last_comp_version
+            self.boot_cpuid_phys,
+            strings.len() as u32,
+            structs.len() as u32,
+        ] {
+            dtb.extend_from_slice(&field.to_be_bytes());
+        }
+        for (address, size) in self.reservations.iter().chain([(0, 0)].iter()) {
+            dtb.extend_from_slice(&address.to_be_bytes());
+            dtb.extend_from_slice(&size.to_be_bytes());
+        }
+        dtb.extend_from_slice(&structs);
+        dtb.extend_from_slice(&strings);
+
+        dtb
+    }
+
+    /// Resolves the path of the target node of an overlay fragment.
+    fn fragment_target(&self, fragment: &Node) -> DeviceTreeResult<String> {
+        if let Some(phandle) = fragment.property("target").and_then(|v| be32(v, 0)) {
+            match self.root.path_of_phandle(phandle) {
+                Some(path) if path.is_empty() => Ok("/".to_string()),
+                Some(path) => Ok(path),
+                None => {
+                    warn!(
+                        "    Fragment {:?} target phandle {phandle} not found",
+                        fragment.name
+                    );
+                    error(DeviceTreeError::TargetNotFound)
+                }
+            }
+        } else if let Some(path) = fragment.property_str("target-path") {
+            if self.root.find(path).is_some() {
+                Ok(path.to_string())
+            } else {
+                warn!(
+                    "    Fragment {:?} target-path {path:?} not found",
+                    fragment.name
+                );
+                error(DeviceTreeError::TargetNotFound)
+            }
+        } else {
+            warn!("    Fragment {:?} has no target", fragment.name);
+            error(DeviceTreeError::MissingTarget)
+        }
+    }
+
+    /// Resolves the `__fixups__` of the overlay against the `__symbols__` of this tree.
+    fn fixup_phandles(&self, overlay: &mut Node) -> DeviceTreeResult<()> {
+        let Some(fixups) = overlay.child("__fixups__").cloned() else {
+            return Ok(());
+        };
+        let symbols = self.root.child("__symbols__");
+
+        for fixup in fixups.props.iter() {
+            let label = fixup.name.as_str();
+            let Some(symbol_path) = symbols.and_then(|symbols| symbols.property_str(label)) else {
+                warn!("    Label {label:?} not found in base __symbols__");
+                return error(DeviceTreeError::SymbolNotFound);
+            };
+            let Some(phandle) = self.root.find(symbol_path).and_then(Node::phandle) else {
+                warn!("    Node {symbol_path:?} for label {label:?} has no phandle");
+                return error(DeviceTreeError::SymbolNotFound);
+            };
+
+            let fixup = core::str::from_utf8(&fixup.value).unwrap_or("");
+            for location in fixup.split('\0').filter(|s| !s.is_empty()) {
+                // `path:property:offset`
+                let mut parts = location.rsplitn(3, ':');
+                let (Some(offset), Some(prop), Some(path)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    warn!("    Malformed fixup {location:?}");
+                    return error(DeviceTreeError::BadFixup);
+                };
+                let Ok(offset) = offset.parse::<usize>() else {
+                    warn!("    Malformed fixup offset {location:?}");
+                    return error(DeviceTreeError::BadFixup);
+                };
+                match overlay
+                    .find_mut(path)
+                    .and_then(|node| node.property_mut(prop))
+                    .and_then(|value| value.get_mut(offset..offset.checked_add(4)?))
+                {
+                    Some(cell) => cell.copy_from_slice(&phandle.to_be_bytes()),
+                    None => {
+                        warn!("    Fixup location {location:?} not found in overlay");
+                        return error(DeviceTreeError::BadFixup);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the overlay `__symbols__` to this tree, rewritten to their merged location.
+    fn update_symbols(&mut self, overlay: &Node, targets: &[(String, String)]) {
+        let Some(overlay_symbols) = overlay.child("__symbols__") else {
+            return;
+        };
+        for symbol in overlay_symbols.props.iter() {
+            let Ok(path) = core::str::from_utf8(&symbol.value) else {
+                continue;
+            };
+            let path = path.trim_end_matches('\0');
+            // Symbols are of the form `/fragment@N/__overlay__/rest`.
+            let rewritten = targets.iter().find_map(|(fragment, target)| {
+                let rest = path
+                    .strip_prefix(fragment.as_str())?
+                    .strip_prefix("/__overlay__")?;
+                if target == "/" && !rest.is_empty() {
+                    Some(rest.to_string())
+                } else {
+                    Some(format!("{target}{rest}"))
+                }
+            });
+            if let Some(rewritten) = rewritten {
+                let mut value = rewritten.into_bytes();
+                value.push(0);
+                self.root
+                    .child_or_insert("__symbols__")
+                    .set_property(&symbol.name, &value);
+            }
+        }
+    }
+
+    /// Applies a compiled overlay (`dtc -@`) to this tree.
+    ///
+    /// On error, the tree may be partially modified; apply on a clone to keep it pristine.
+    pub fn apply_overlay(&mut self, overlay: &Fdt) -> DeviceTreeResult<()> {
+        let mut overlay = DeviceTree::from_fdt(overlay)?.root;
+
+        // Make room for the overlay phandles, after the ones of the base tree.
+        let delta = self.root.max_phandle();
+        overlay.adjust_phandles(delta)?;
+        if let Some(local_fixups) = overlay.child("__local_fixups__").cloned() {
+            overlay.adjust_local_references(&local_fixups, delta)?;
+        }
+
+        self.fixup_phandles(&mut overlay)?;
+
+        let mut targets = Vec::new();
+        for fragment in overlay.children.iter() {
+            let Some(contents) = fragment.child("__overlay__") else {
+                continue;
+            };
+            let target = self.fragment_target(fragment)?;
+            debug!("    Merging {:?} into {:?}", fragment.name, target);
+            match self.root.find_mut(&target) {
+                Some(node) => node.merge(contents),
+                None => return error(DeviceTreeError::TargetNotFound),
+            }
+            targets.push((format!("/{}", fragment.name), target));
+        }
+
+        self.update_symbols(&overlay, &targets);
+
+        Ok(())
+    }
+}
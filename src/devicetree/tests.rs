@@ -0,0 +1,222 @@
+//! Tests against dtb and dtbo blobs.
+
+use super::*;
+
+/// Base dtb, laid out as `dtc -@` compiles:
+///
+/// ```dts
+/// /dts-v1/;
+/// /memreserve/ 0x80000000 0x100000;
+/// / {
+///     compatible = "test,base";
+///     #address-cells = <1>;
+///     #size-cells = <1>;
+///     soc {
+///         compatible = "simple-bus";
+///         #address-cells = <1>;
+///         #size-cells = <1>;
+///         ranges;
+///         uart0: serial@1000 {
+///             compatible = "ns16550a";
+///             reg = <0x1000 0x100>;
+///             status = "disabled";
+///         };
+///         gpio: gpio@2000 {
+///             reg = <0x2000 0x100>;
+///             gpio-controller;
+///             #gpio-cells = <2>;
+///         };
+///     };
+/// };
+/// ```
+const BASE: &[u8] = &[
+    0xd0, 0x0d, 0xfe, 0xed, 0x00, 0x00, 0x02, 0x53, 0x00, 0x00, 0x00, 0x48, 0x00, 0x00, 0x01, 0xec,
+    0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x67, 0x00, 0x00, 0x01, 0xa4, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x74, 0x65, 0x73, 0x74,
+    0x2c, 0x62, 0x61, 0x73, 0x65, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x73, 0x6f, 0x63, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x73, 0x69, 0x6d, 0x70,
+    0x6c, 0x65, 0x2d, 0x62, 0x75, 0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x26, 0x00, 0x00, 0x00, 0x01, 0x73, 0x65, 0x72, 0x69, 0x61, 0x6c, 0x40, 0x31,
+    0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00,
+    0x6e, 0x73, 0x31, 0x36, 0x35, 0x35, 0x30, 0x61, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x31, 0x64, 0x69, 0x73, 0x61,
+    0x62, 0x6c, 0x65, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04,
+    0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+    0x67, 0x70, 0x69, 0x6f, 0x40, 0x32, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x5f, 0x5f, 0x73, 0x79, 0x6d, 0x62, 0x6f, 0x6c,
+    0x73, 0x5f, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x5c,
+    0x2f, 0x73, 0x6f, 0x63, 0x2f, 0x73, 0x65, 0x72, 0x69, 0x61, 0x6c, 0x40, 0x31, 0x30, 0x30, 0x30,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x62,
+    0x2f, 0x73, 0x6f, 0x63, 0x2f, 0x67, 0x70, 0x69, 0x6f, 0x40, 0x32, 0x30, 0x30, 0x30, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x09, 0x63, 0x6f, 0x6d, 0x70,
+    0x61, 0x74, 0x69, 0x62, 0x6c, 0x65, 0x00, 0x23, 0x61, 0x64, 0x64, 0x72, 0x65, 0x73, 0x73, 0x2d,
+    0x63, 0x65, 0x6c, 0x6c, 0x73, 0x00, 0x23, 0x73, 0x69, 0x7a, 0x65, 0x2d, 0x63, 0x65, 0x6c, 0x6c,
+    0x73, 0x00, 0x72, 0x61, 0x6e, 0x67, 0x65, 0x73, 0x00, 0x72, 0x65, 0x67, 0x00, 0x73, 0x74, 0x61,
+    0x74, 0x75, 0x73, 0x00, 0x70, 0x68, 0x61, 0x6e, 0x64, 0x6c, 0x65, 0x00, 0x67, 0x70, 0x69, 0x6f,
+    0x2d, 0x63, 0x6f, 0x6e, 0x74, 0x72, 0x6f, 0x6c, 0x6c, 0x65, 0x72, 0x00, 0x23, 0x67, 0x70, 0x69,
+    0x6f, 0x2d, 0x63, 0x65, 0x6c, 0x6c, 0x73, 0x00, 0x75, 0x61, 0x72, 0x74, 0x30, 0x00, 0x67, 0x70,
+    0x69, 0x6f, 0x00,
+];
+
+/// Overlay, laid out as `dtc -@` compiles:
+///
+/// ```dts
+/// /dts-v1/;
+/// /plugin/;
+/// &uart0 {
+///     status = "okay";
+/// };
+/// &{/soc} {
+///     led: led {
+///         gpios = <&gpio 5 0>;
+///     };
+///     leds-user {
+///         led-handle = <&led>;
+///     };
+/// };
+/// ```
+const OVERLAY: &[u8] = &[
+    0xd0, 0x0d, 0xfe, 0xed, 0x00, 0x00, 0x02, 0x76, 0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x02, 0x34,
+    0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x42, 0x00, 0x00, 0x01, 0xfc, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x01, 0x66, 0x72, 0x61, 0x67, 0x6d, 0x65, 0x6e, 0x74, 0x40, 0x30, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+    0x00, 0x00, 0x00, 0x01, 0x5f, 0x5f, 0x6f, 0x76, 0x65, 0x72, 0x6c, 0x61, 0x79, 0x5f, 0x5f, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x07, 0x6f, 0x6b, 0x61, 0x79,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+    0x66, 0x72, 0x61, 0x67, 0x6d, 0x65, 0x6e, 0x74, 0x40, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x0e, 0x2f, 0x73, 0x6f, 0x63, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x01, 0x5f, 0x5f, 0x6f, 0x76, 0x65, 0x72, 0x6c, 0x61, 0x79, 0x5f, 0x5f, 0x00,
+    0x00, 0x00, 0x00, 0x01, 0x6c, 0x65, 0x64, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x0c,
+    0x00, 0x00, 0x00, 0x1a, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x01,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x6c, 0x65, 0x64, 0x73, 0x2d, 0x75, 0x73, 0x65,
+    0x72, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x28,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x01, 0x5f, 0x5f, 0x73, 0x79, 0x6d, 0x62, 0x6f, 0x6c, 0x73, 0x5f, 0x5f, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x33, 0x2f, 0x66, 0x72, 0x61,
+    0x67, 0x6d, 0x65, 0x6e, 0x74, 0x40, 0x31, 0x2f, 0x5f, 0x5f, 0x6f, 0x76, 0x65, 0x72, 0x6c, 0x61,
+    0x79, 0x5f, 0x5f, 0x2f, 0x6c, 0x65, 0x64, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+    0x5f, 0x5f, 0x66, 0x69, 0x78, 0x75, 0x70, 0x73, 0x5f, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, 0x37, 0x2f, 0x66, 0x72, 0x61, 0x67, 0x6d, 0x65, 0x6e,
+    0x74, 0x40, 0x30, 0x3a, 0x74, 0x61, 0x72, 0x67, 0x65, 0x74, 0x3a, 0x30, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00, 0x3d, 0x2f, 0x66, 0x72, 0x61,
+    0x67, 0x6d, 0x65, 0x6e, 0x74, 0x40, 0x31, 0x2f, 0x5f, 0x5f, 0x6f, 0x76, 0x65, 0x72, 0x6c, 0x61,
+    0x79, 0x5f, 0x5f, 0x2f, 0x6c, 0x65, 0x64, 0x3a, 0x67, 0x70, 0x69, 0x6f, 0x73, 0x3a, 0x30, 0x00,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x5f, 0x5f, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x5f,
+    0x66, 0x69, 0x78, 0x75, 0x70, 0x73, 0x5f, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x66, 0x72, 0x61, 0x67, 0x6d, 0x65, 0x6e, 0x74, 0x40, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x5f, 0x5f, 0x6f, 0x76, 0x65, 0x72, 0x6c, 0x61, 0x79, 0x5f, 0x5f, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x6c, 0x65, 0x64, 0x73, 0x2d, 0x75, 0x73, 0x65, 0x72, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x00, 0x00, 0x09, 0x74, 0x61, 0x72, 0x67, 0x65, 0x74, 0x00, 0x73, 0x74, 0x61, 0x74, 0x75,
+    0x73, 0x00, 0x74, 0x61, 0x72, 0x67, 0x65, 0x74, 0x2d, 0x70, 0x61, 0x74, 0x68, 0x00, 0x67, 0x70,
+    0x69, 0x6f, 0x73, 0x00, 0x70, 0x68, 0x61, 0x6e, 0x64, 0x6c, 0x65, 0x00, 0x6c, 0x65, 0x64, 0x2d,
+    0x68, 0x61, 0x6e, 0x64, 0x6c, 0x65, 0x00, 0x6c, 0x65, 0x64, 0x00, 0x75, 0x61, 0x72, 0x74, 0x30,
+    0x00, 0x67, 0x70, 0x69, 0x6f, 0x00,
+];
+
+/// Applies [`OVERLAY`] on [`BASE`].
+fn applied() -> DeviceTree {
+    let mut tree = DeviceTree::from_bytes(BASE).unwrap();
+    tree.apply_overlay(&Fdt::new(OVERLAY).unwrap()).unwrap();
+    tree
+}
+
+fn cell(tree: &DeviceTree, path: &str, name: &str, index: usize) -> Option<u32> {
+    be32(tree.root.find(path)?.property(name)?, index * 4)
+}
+
+#[test]
+fn fixups_resolve_targets() {
+    let tree = applied();
+    // `&uart0`, through `target` and `__fixups__`.
+    let serial = tree.root.find("/soc/serial@1000").unwrap();
+    assert_eq!(serial.property_str("status"), Some("okay"));
+    assert_eq!(serial.property_str("compatible"), Some("ns16550a"));
+    // `&gpio`, within a property.
+    assert_eq!(cell(&tree, "/soc/led", "gpios", 0), Some(2));
+    assert_eq!(cell(&tree, "/soc/led", "gpios", 1), Some(5));
+}
+
+#[test]
+fn phandle_delta_and_local_fixups() {
+    let tree = applied();
+    // Offset past the base phandles.
+    assert_eq!(tree.root.find("/soc/led").and_then(Node::phandle), Some(3));
+    assert_eq!(cell(&tree, "/soc/leds-user", "led-handle", 0), Some(3));
+    assert_eq!(
+        tree.root.find("/soc/gpio@2000").and_then(Node::phandle),
+        Some(2)
+    );
+}
+
+#[test]
+fn symbols_rewritten() {
+    let tree = applied();
+    let symbols = tree.root.child("__symbols__").unwrap();
+    assert_eq!(symbols.property_str("led"), Some("/soc/led"));
+    assert_eq!(symbols.property_str("uart0"), Some("/soc/serial@1000"));
+    assert!(tree.root.child("fragment@1").is_none());
+}
+
+#[test]
+fn phandle_overflow_rejected() {
+    let mut tree = DeviceTree::from_bytes(BASE).unwrap();
+    tree.root
+        .find_mut("/soc/gpio@2000")
+        .unwrap()
+        .set_property("phandle", &u32::MAX.to_be_bytes());
+    assert_eq!(
+        tree.apply_overlay(&Fdt::new(OVERLAY).unwrap()),
+        Err(DeviceTreeError::PhandleOverflow)
+    );
+}
+
+#[test]
+fn missing_symbol_rejected() {
+    let mut tree = DeviceTree::from_bytes(BASE).unwrap();
+    tree.root.child_mut("__symbols__").unwrap().props.clear();
+    assert_eq!(
+        tree.apply_overlay(&Fdt::new(OVERLAY).unwrap()),
+        Err(DeviceTreeError::SymbolNotFound)
+    );
+}
+
+#[test]
+fn round_trip() {
+    // Flattened back as compiled.
+    assert_eq!(DeviceTree::from_bytes(BASE).unwrap().to_dtb(), BASE);
+
+    let dtb = applied().to_dtb();
+    let fdt = Fdt::new(&dtb).unwrap();
+    assert_eq!(fdt.total_size(), dtb.len());
+    let reservations: Vec<_> = fdt
+        .memory_reservations()
+        .map(|reservation| (reservation.address() as usize, reservation.size()))
+        .collect();
+    assert_eq!(reservations, [(0x8000_0000, 0x10_0000)]);
+
+    let serial = fdt.find_node("/soc/serial@1000").unwrap();
+    assert_eq!(serial.property("status").unwrap().as_str(), Some("okay"));
+    let led = fdt.find_phandle(3).unwrap();
+    assert_eq!(led.name, "led");
+    let gpio = fdt.find_node("/soc/gpio@2000").unwrap();
+    assert!(gpio.property("gpio-controller").unwrap().value.is_empty());
+    let symbols = fdt.find_node("/__symbols__").unwrap();
+    assert_eq!(symbols.property("led").unwrap().as_str(), Some("/soc/led"));
+}
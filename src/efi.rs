@@ -2,11 +2,22 @@
 
 use crate::protocols::dt_fixup::DtFixup;
 use crate::protocols::dt_fixup::DtFixupFlags;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
 use core::ffi::c_void;
 use log::debug;
 use log::warn;
 use uefi::prelude::*;
+use uefi::proto::console::text::Key;
+use uefi::table::boot::EventType;
 use uefi::table::boot::SearchType;
+use uefi::table::boot::TimerTrigger;
+use uefi::table::boot::Tpl;
+use uefi::table::runtime::VariableAttributes;
+use uefi::table::runtime::VariableVendor;
+use uefi::CStr16;
+use uefi::CString16;
 use uefi::Identify;
 use uefi::Result;
 use uefi::{guid, Guid};
@@ -29,6 +40,13 @@ pub unsafe fn install_efi_dtb_table(st: &SystemTable<Boot>, fdt: *const c_void)
     boot_services.install_configuration_table(&EFI_DTB_TABLE_GUID, fdt)
 }
 
+/// Removes the currently installed FDT, if any.
+pub unsafe fn uninstall_efi_dtb_table(st: &SystemTable<Boot>) -> Result {
+    debug!("-> Uninstalling EFI_DTB_TABLE...");
+    let boot_services = st.boot_services();
+    boot_services.install_configuration_table(&EFI_DTB_TABLE_GUID, core::ptr::null())
+}
+
 /// Calls the EFI_DT_FIXUP_PROTOCOL
 pub fn efi_dt_fixup(
     st: &SystemTable<Boot>,
@@ -63,3 +81,151 @@ pub fn get_efi_smbios3_table(st: &SystemTable<Boot>) -> Option<*const c_void> {
         .find(|config| config.guid == EFI_SMBIOS3_TABLE_GUID)
         .map(|config| config.address)
 }
+
+const EFI_SMBIOS_TABLE_GUID: Guid = guid!("eb9d2d31-2d88-11d3-9a16-0090273fc14d");
+
+/// Gets the currently installed (32-bit entry point) SMBIOS table.
+pub fn get_efi_smbios_table(st: &SystemTable<Boot>) -> Option<*const c_void> {
+    debug!("-> Getting EFI_SMBIOS_TABLE...");
+    st.config_table()
+        .iter()
+        .find(|config| config.guid == EFI_SMBIOS_TABLE_GUID)
+        .map(|config| config.address)
+}
+
+/// Vendor of fdtshim's own EFI variables.
+///
+/// fdtshim publishes the following volatile variables, as strings, for the OS:
+///
+///  - `FdtshimVersion`: version of fdtshim.
+///  - `FdtshimMappingVersion`: `linux,kernel-version` of the mapping, when given.
+///  - `FdtshimSelectedDtb`: path of the installed dtb, as in the mapping.
+///  - `FdtshimSelectedEntry`: name of the mapping node of the installed dtb.
+///  - `FdtshimMatchMethod`: `compatible`, `dmi-match` or `compatible+dmi-match` for a scored
+///    match, `override` for the user's override or menu choice, including keeping the firmware
+///    FDT or removing it, `none` when no dtb matched.
+///  - `FdtshimDryRunResult`: the decision in dry-run mode, see [`crate::config`].
+///  - `FdtshimLog`: the last log records, as UTF-8, see [`crate::logger`].
+///
+/// It reads the following, set by the user:
+///
+///  - `FdtshimExplain`, `FdtshimDryRun`: toggles, see [`crate::config`].
+///  - `FdtshimOverride`, `FdtshimOverrideOneShot`: dtb override, see [`crate::matching`].
+pub const FDTSHIM_VARIABLE_VENDOR: VariableVendor =
+    VariableVendor(guid!("0bd9ecb9-910b-441f-8689-56826c5fac29"));
+
+/// Reads one of fdtshim's own EFI variables, if set.
+pub fn get_fdtshim_variable(st: &SystemTable<Boot>, name: &CStr16) -> Option<Vec<u8>> {
+    debug!("-> Getting EFI variable {}...", name);
+    st.runtime_services()
+        .get_variable_boxed(name, &FDTSHIM_VARIABLE_VENDOR)
+        .ok()
+        .map(|(data, _)| data.into_vec())
+}
+
+/// Reads one of fdtshim's own EFI variables as a string.
+///
+/// Both UCS-2, as with systemd's `Loader*` variables, and UTF-8 are accepted,
+/// ignoring trailing NULs and whitespace.
+pub fn get_fdtshim_string_variable(st: &SystemTable<Boot>, name: &CStr16) -> Option<String> {
+    let data = get_fdtshim_variable(st, name)?;
+    let value = if data.len() % 2 == 0 && data.iter().skip(1).step_by(2).all(|b| *b == 0) {
+        let chars: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16(&chars).ok()?
+    } else {
+        String::from_utf8(data).ok()?
+    };
+
+    Some(
+        value
+            .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string(),
+    )
+}
+
+/// Deletes one of fdtshim's own EFI variables.
+pub fn delete_fdtshim_variable(st: &SystemTable<Boot>, name: &CStr16) -> Result {
+    debug!("-> Deleting EFI variable {}...", name);
+    st.runtime_services()
+        .delete_variable(name, &FDTSHIM_VARIABLE_VENDOR)
+}
+
+/// Sets one of fdtshim's own EFI variables to a string, as UCS-2, like systemd's `Loader*` ones.
+///
+/// The variable is volatile, and readable by the OS, e.g. from `efivarfs`.
+pub fn set_fdtshim_variable(st: &SystemTable<Boot>, name: &CStr16, value: &str) -> Result {
+    set_fdtshim_string_variable(st, name, value, VariableAttributes::empty())
+}
+
+/// Sets one of fdtshim's own EFI variables to a string, kept across reboots.
+pub fn persist_fdtshim_variable(st: &SystemTable<Boot>, name: &CStr16, value: &str) -> Result {
+    set_fdtshim_string_variable(st, name, value, VariableAttributes::NON_VOLATILE)
+}
+
+/// Sets one of fdtshim's own EFI variables to raw bytes, volatile, and readable by the OS.
+pub fn set_fdtshim_variable_bytes(st: &SystemTable<Boot>, name: &CStr16, data: &[u8]) -> Result {
+    set_fdtshim_variable_with(st, name, data, VariableAttributes::empty())
+}
+
+fn set_fdtshim_string_variable(
+    st: &SystemTable<Boot>,
+    name: &CStr16,
+    value: &str,
+    attributes: VariableAttributes,
+) -> Result {
+    debug!("-> Setting EFI variable {} to {:?}...", name, value);
+    let value = CString16::try_from(value).map_err(|_| Status::INVALID_PARAMETER)?;
+    set_fdtshim_variable_with(st, name, value.as_bytes(), attributes)
+}
+
+fn set_fdtshim_variable_with(
+    st: &SystemTable<Boot>,
+    name: &CStr16,
+    data: &[u8],
+    attributes: VariableAttributes,
+) -> Result {
+    st.runtime_services().set_variable(
+        name,
+        &FDTSHIM_VARIABLE_VENDOR,
+        attributes | VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS,
+        data,
+    )
+}
+
+/// Waits for a key press on the console.
+pub fn wait_for_key() {
+    let mut st = uefi::helpers::system_table();
+    let _ = st.stdin().reset(false);
+    if let Some(event) = st.stdin().wait_for_key_event() {
+        let _ = st.boot_services().wait_for_event(&mut [event]);
+    }
+    let _ = st.stdin().read_key();
+}
+
+/// Waits up to `timeout` seconds for a key press on the console, and reads it.
+///
+/// Without a timeout, only a key already pressed is read.
+pub fn read_key_timeout(timeout: usize) -> Option<Key> {
+    let mut st = uefi::helpers::system_table();
+    if timeout > 0 {
+        let key_event = st.stdin().wait_for_key_event()?;
+        let bs = st.boot_services();
+        let timer = unsafe { bs.create_event(EventType::TIMER, Tpl::CALLBACK, None, None) }.ok()?;
+        let index = bs
+            .set_timer(&timer, TimerTrigger::Relative(timeout as u64 * 10_000_000))
+            .ok()
+            .and_then(|_| {
+                bs.wait_for_event(&mut [key_event, unsafe { timer.unsafe_clone() }])
+                    .ok()
+            });
+        let _ = bs.close_event(timer);
+        if index != Some(0) {
+            return None;
+        }
+    }
+
+    st.stdin().read_key().ok().flatten()
+}
@@ -0,0 +1,27 @@
+//! Global state.
+
+use core::cell::UnsafeCell;
+
+/// A global value, without synchronization.
+pub struct Global<T>(UnsafeCell<T>);
+// SAFETY: UEFI boot services run single-threaded.
+unsafe impl<T> Sync for Global<T> {}
+unsafe impl<T> Send for Global<T> {}
+
+impl<T> Global<T> {
+    pub const fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    pub fn get(&self) -> &T {
+        // SAFETY: No mutable reference is used meanwhile, as per `get_mut`.
+        unsafe { &*self.0.get() }
+    }
+
+    /// # Safety
+    /// No other reference to the value may be used while the returned one is live.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut(&self) -> &mut T {
+        &mut *self.0.get()
+    }
+}
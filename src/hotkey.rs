@@ -0,0 +1,42 @@
+//! Keys changing fdtshim's behaviour at startup.
+//!
+//! A key can be held at startup, or pressed during the configured `timeout`:
+//!
+//!  - `d`: debug log,
+//!  - `m` or `Esc`: selection menu, see [`crate::menu`],
+//!  - `s`: skip the dtb selection, keeping the firmware FDT, and start the next stage.
+
+use crate::efi::read_key_timeout;
+use log::debug;
+use log::info;
+use uefi::proto::console::text::Key;
+use uefi::proto::console::text::ScanCode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    Debug,
+    Menu,
+    Skip,
+}
+
+/// Waits up to `timeout` seconds for a hotkey.
+///
+/// Without a timeout, there is no added latency; only a key held at startup is read.
+pub fn startup_hotkey(timeout: usize) -> Option<Hotkey> {
+    if timeout > 0 {
+        info!("Press d (debug log), m (menu) or s (skip the dtb selection) within {timeout}s...");
+    }
+    let hotkey = match read_key_timeout(timeout)? {
+        Key::Special(ScanCode::ESCAPE) => Some(Hotkey::Menu),
+        Key::Printable(c) => match char::from(c) {
+            'd' | 'D' => Some(Hotkey::Debug),
+            'm' | 'M' => Some(Hotkey::Menu),
+            's' | 'S' => Some(Hotkey::Skip),
+            _ => None,
+        },
+        _ => None,
+    };
+    debug!("Startup hotkey: {:?}", hotkey);
+
+    hotkey
+}
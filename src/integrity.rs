@@ -0,0 +1,92 @@
+//! Integrity verification of the matched dtb files.
+//!
+//! A `/mapping` entry can record the expected `size` (in bytes) and `sha256` digest of its
+//! dtb, checked against the file as loaded, before applying the overlays:
+//!
+//! ```dts
+//! rockchip@rk3399-pinebook-pro {
+//!     dtb = "rockchip/rk3399-pinebook-pro.dtb";
+//!     size = <62345>;
+//!     sha256 = [9a 1e ... 5c 07];
+//! };
+//! ```
+//!
+//! On a mismatch, the dtb is refused, and the `integrity-policy` configuration decides
+//! what happens instead, see [`IntegrityPolicy`].
+
+use crate::matching::MatchedDTB;
+use crate::sha256::sha256;
+use alloc::string::String;
+use core::fmt::Write;
+use log::debug;
+use log::error;
+
+/// What to do when a dtb fails the integrity verification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IntegrityPolicy {
+    /// Keep the firmware FDT, and continue booting (default).
+    #[default]
+    Firmware,
+    /// Remove the firmware FDT, and continue booting.
+    NoFdt,
+    /// Return to the firmware boot manager, as for other dtb errors.
+    Abort,
+}
+
+impl IntegrityPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "firmware" => Some(Self::Firmware),
+            "none" => Some(Self::NoFdt),
+            "abort" => Some(Self::Abort),
+            _ => None,
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// Checks the loaded dtb against the `size` and `sha256` recorded in the mapping, if any.
+pub fn verify_dtb(matched_dtb: &MatchedDTB, dtb: &[u8]) -> bool {
+    if let Some(size) = matched_dtb.size {
+        if dtb.len() != size {
+            error!(
+                "Size mismatch for {:?}: expected {size} bytes, got {}",
+                matched_dtb.dtb_path,
+                dtb.len()
+            );
+            return false;
+        }
+        debug!("    (Size verified: {size} bytes)");
+    }
+
+    if let Some(expected) = matched_dtb.sha256 {
+        if expected.len() != 32 {
+            error!(
+                "Invalid sha256 for {:?} in the mapping: {} bytes instead of 32",
+                matched_dtb.name,
+                expected.len()
+            );
+            return false;
+        }
+        let digest = sha256(dtb);
+        if digest != expected {
+            error!(
+                "SHA-256 mismatch for {:?}: expected {}, got {}",
+                matched_dtb.dtb_path,
+                hex(expected),
+                hex(&digest)
+            );
+            return false;
+        }
+        debug!("    (SHA-256 verified: {})", hex(&digest));
+    }
+
+    true
+}
@@ -0,0 +1,10 @@
+//! Parsers independent from UEFI, shared with the `fdtshim` application,
+//! and tested on the host with `cargo test`.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod devicetree;
+pub mod load_options;
+pub mod sha256;
+pub mod smbios;
@@ -0,0 +1,285 @@
+//! Parsing of fdtshim's own load options, as a command line.
+//!
+//! `argv[0]`, the image name, is only given by the UEFI Shell; `Boot####` entries
+//! and boot loaders pass the options alone:
+//!
+//! ```text
+//! [fdtshim.efi] [fdtshim options] [--] [next.efi [arguments...]]
+//! ```
+//!
+//! fdtshim options are of the form `--name` or `--name=value`. Everything
+//! following the next stage is forwarded verbatim as its load options.
+
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use log::debug;
+use log::warn;
+
+/// Options understood by fdtshim.
+const KNOWN_OPTIONS: &[&str] = &[
+    "config",
+    "prefix",
+    "mapping",
+    "volume",
+    "explain",
+    "dry-run",
+    "timeout",
+    "menu",
+    "menu-timeout",
+    "log-level",
+    "log",
+    "log-file",
+    "integrity-policy",
+];
+
+#[derive(Default)]
+pub struct LoadOptions {
+    /// fdtshim options, in order, as `(name, value)`.
+    pub options: Vec<(String, Option<String>)>,
+    pub next_stage: Option<String>,
+    /// Command line remainder following the next stage.
+    pub next_stage_args: String,
+}
+
+/// Splits the command line in words, honouring double quotes.
+///
+/// Returns the words along with the byte offset following them.
+fn split_words(cmdline: &str) -> Vec<(String, usize)> {
+    let mut words = Vec::new();
+    let mut chars = cmdline.char_indices().peekable();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut word = String::new();
+        let mut quoted = false;
+        let mut end = cmdline.len();
+        for (i, c) in chars.by_ref() {
+            match c {
+                '"' => quoted = !quoted,
+                c if c.is_whitespace() && !quoted => {
+                    end = i;
+                    break;
+                }
+                c => word.push(c),
+            }
+        }
+        words.push((word, end));
+    }
+
+    words
+}
+
+/// Whether the command line word `word` names the image at `image_path`.
+///
+/// The UEFI Shell passes the image as typed, so only the file names are compared,
+/// case-insensitively as on FAT, with an optional `.efi` extension.
+fn names_image(word: &str, image_path: &str) -> bool {
+    let file_name = |path: &str| -> String {
+        path.rsplit(['\\', '/', ':'])
+            .next()
+            .unwrap_or(path)
+            .to_ascii_lowercase()
+    };
+    let (word, image) = (file_name(word), file_name(image_path));
+    !image.is_empty() && (word == image || image.strip_suffix(".efi") == Some(word.as_str()))
+}
+
+impl LoadOptions {
+    /// Parses the load options of the image at `image_path`.
+    ///
+    /// The first word is skipped as `argv[0]` when it names the image. When the image path
+    /// is unknown, it is skipped unless it is an fdtshim option.
+    pub fn new(cmdline: &str, image_path: Option<&str>) -> Self {
+        let mut load_options = Self::default();
+        let mut words = split_words(cmdline).into_iter().peekable();
+        if let Some((argv0, _)) = words.peek() {
+            let is_argv0 = match image_path {
+                Some(image_path) => names_image(argv0, image_path),
+                None => !argv0.starts_with("--"),
+            };
+            if is_argv0 {
+                debug!("Skipping argv[0] {:?}", argv0);
+                words.next();
+            }
+        }
+
+        while let Some((word, end)) = words.next() {
+            let next_stage = if word == "--" {
+                words.next()
+            } else if let Some(option) = word.strip_prefix("--") {
+                let (name, value) = match option.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (option, None),
+                };
+                if !KNOWN_OPTIONS.contains(&name) {
+                    warn!("Unknown option {:?}", word);
+                }
+                load_options.options.push((name.to_string(), value));
+                continue;
+            } else {
+                Some((word, end))
+            };
+
+            if let Some((next_stage, end)) = next_stage {
+                load_options.next_stage = Some(next_stage);
+                load_options.next_stage_args = cmdline[end..].trim().to_string();
+            }
+            break;
+        }
+
+        load_options
+    }
+
+    /// The value of the last occurence of an fdtshim option.
+    ///
+    /// Options given without a value are returned as an empty string.
+    pub fn option<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        self.option_values(name).last()
+    }
+
+    /// The values of all occurences of an fdtshim option, in order.
+    pub fn option_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.options
+            .iter()
+            .filter(move |(option, _)| option == name)
+            .map(|(_, value)| value.as_deref().unwrap_or(""))
+    }
+
+    /// The next stage, if given.
+    pub fn next_stage(&self) -> Option<&str> {
+        self.next_stage.as_deref()
+    }
+
+    /// Arguments following the next stage, to be forwarded to it.
+    pub fn next_stage_args(&self) -> Option<&str> {
+        if self.next_stage_args.is_empty() {
+            None
+        } else {
+            Some(&self.next_stage_args)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: Option<&str> = Some(r"\EFI\fdtshim\fdtshim.efi");
+
+    fn names(load_options: &LoadOptions) -> Vec<&str> {
+        load_options
+            .options
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn shell_argv0() {
+        for argv0 in [
+            "fdtshim.efi",
+            "FDTSHIM",
+            r"fs0:\EFI\fdtshim\fdtshim.efi",
+            r".\fdtshim.efi",
+        ] {
+            let load_options = LoadOptions::new(&format!("{argv0} --explain"), IMAGE);
+            assert_eq!(names(&load_options), ["explain"], "{argv0}");
+            assert_eq!(load_options.next_stage(), None);
+        }
+    }
+
+    #[test]
+    fn missing_argv0() {
+        let load_options = LoadOptions::new(r"--explain -- \EFI\Linux\k.efi root=/dev/sda2", IMAGE);
+        assert_eq!(names(&load_options), ["explain"]);
+        assert_eq!(load_options.next_stage(), Some(r"\EFI\Linux\k.efi"));
+        assert_eq!(load_options.next_stage_args(), Some("root=/dev/sda2"));
+
+        let load_options = LoadOptions::new(r"\EFI\Linux\k.efi root=/dev/sda2", IMAGE);
+        assert_eq!(load_options.next_stage(), Some(r"\EFI\Linux\k.efi"));
+        assert_eq!(load_options.next_stage_args(), Some("root=/dev/sda2"));
+    }
+
+    #[test]
+    fn unknown_image_path() {
+        let load_options = LoadOptions::new("--dry-run next.efi", None);
+        assert_eq!(names(&load_options), ["dry-run"]);
+        assert_eq!(load_options.next_stage(), Some("next.efi"));
+
+        let load_options = LoadOptions::new("fdtshim.efi --dry-run", None);
+        assert_eq!(names(&load_options), ["dry-run"]);
+        assert_eq!(load_options.next_stage(), None);
+    }
+
+    #[test]
+    fn options() {
+        let load_options = LoadOptions::new(
+            "fdtshim.efi --prefix=a --prefix=b --explain --log-level=debug",
+            IMAGE,
+        );
+        assert_eq!(
+            load_options.option_values("prefix").collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(load_options.option("prefix"), Some("b"));
+        assert_eq!(load_options.option("explain"), Some(""));
+        assert_eq!(load_options.option("log-level"), Some("debug"));
+        assert_eq!(load_options.option("dry-run"), None);
+    }
+
+    #[test]
+    fn quoting() {
+        let load_options = LoadOptions::new(
+            r#"fdtshim.efi --mapping="\EFI\my dtbs\mapping.dtb" "\EFI\my boot\grub.efi" a"#,
+            IMAGE,
+        );
+        assert_eq!(
+            load_options.option("mapping"),
+            Some(r"\EFI\my dtbs\mapping.dtb")
+        );
+        assert_eq!(load_options.next_stage(), Some(r"\EFI\my boot\grub.efi"));
+        assert_eq!(load_options.next_stage_args(), Some("a"));
+    }
+
+    #[test]
+    fn double_dash() {
+        // The next stage may look like an option after `--`.
+        let load_options = LoadOptions::new("fdtshim.efi --explain -- --odd.efi --explain", IMAGE);
+        assert_eq!(names(&load_options), ["explain"]);
+        assert_eq!(load_options.next_stage(), Some("--odd.efi"));
+        assert_eq!(load_options.next_stage_args(), Some("--explain"));
+
+        let load_options = LoadOptions::new("fdtshim.efi --explain --", IMAGE);
+        assert_eq!(load_options.next_stage(), None);
+        assert_eq!(load_options.next_stage_args(), None);
+    }
+
+    #[test]
+    fn remainder_verbatim() {
+        // Forwarded as given, including quotes and inner whitespace.
+        let load_options = LoadOptions::new(
+            r#"fdtshim.efi grub.efi  root=/dev/sda2   quiet "a  b" -- --explain "#,
+            IMAGE,
+        );
+        assert!(load_options.options.is_empty());
+        assert_eq!(load_options.next_stage(), Some("grub.efi"));
+        assert_eq!(
+            load_options.next_stage_args(),
+            Some(r#"root=/dev/sda2   quiet "a  b" -- --explain"#)
+        );
+    }
+
+    #[test]
+    fn empty() {
+        for cmdline in ["", "   ", "fdtshim.efi"] {
+            let load_options = LoadOptions::new(cmdline, IMAGE);
+            assert!(load_options.options.is_empty());
+            assert_eq!(load_options.next_stage(), None);
+            assert_eq!(load_options.next_stage_args(), None);
+        }
+    }
+}
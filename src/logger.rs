@@ -0,0 +1,253 @@
+//! Logging to the configured destinations.
+//!
+//!  - `console`: the UEFI console (default),
+//!  - `serial`: the first serial port, through the `EFI_SERIAL_IO_PROTOCOL`,
+//!  - `file`: a file on the first configured volume, written before starting each next stage
+//!    candidate, and before returning to the firmware. The previous log is kept, with an
+//!    `.old` suffix.
+//!
+//! The last log records are also kept in memory, whatever the destinations, and published
+//! as the `FdtshimLog` EFI variable, see [`crate::efi`], at the same points as the log file.
+//! From Linux, e.g. `tail -c +5 /sys/firmware/efi/efivars/FdtshimLog-*`.
+//!
+//! The log level is, in increasing order of precedence, from the mapping's `/fdtshim/log-level`,
+//! the configuration file, the `FdtshimLogLevel` EFI variable, the `--log-level` load option,
+//! and the `d` startup hotkey. See [`crate::config`].
+
+use crate::config::config;
+use crate::efi::set_fdtshim_variable_bytes;
+use crate::global::Global;
+use crate::volumes::selected_volumes;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use uefi::cstr16;
+use uefi::fs::Path;
+use uefi::fs::PathBuf;
+use uefi::prelude::*;
+use uefi::proto::console::serial::Serial;
+use uefi::table::boot::OpenProtocolAttributes;
+use uefi::table::boot::OpenProtocolParams;
+use uefi::CString16;
+
+/// Maximum size of the log records kept in memory.
+///
+/// Kept small, as firmwares commonly limit the size of variables.
+const LOG_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Default log file, when logging to a file.
+pub const DEFAULT_LOG_FILE: &str = r"\EFI\dtbs\fdtshim.log";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogDestination {
+    Console,
+    Serial,
+    File,
+}
+
+impl LogDestination {
+    pub fn parse(destination: &str) -> Option<Self> {
+        match destination {
+            "console" => Some(Self::Console),
+            "serial" => Some(Self::Serial),
+            "file" => Some(Self::File),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a log level, as named by the `log` crate, e.g. `info` or `debug`.
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    level.parse().ok()
+}
+
+struct LoggerState {
+    /// `None` until configured, logging to the console.
+    destinations: Option<Vec<LogDestination>>,
+    /// Handle providing the `EFI_SERIAL_IO_PROTOCOL`.
+    serial: Option<Handle>,
+    /// Log records for the log file, rewritten whole on each flush.
+    file_buffer: String,
+    /// Whether the previous log file was already kept aside, on the first flush.
+    file_rotated: bool,
+    /// The last log records, at most [`LOG_BUFFER_SIZE`] bytes.
+    ring_buffer: VecDeque<String>,
+    ring_buffer_size: usize,
+}
+
+struct Logger(Global<LoggerState>);
+
+static LOGGER: Logger = Logger(Global::new(LoggerState {
+    destinations: None,
+    serial: None,
+    file_buffer: String::new(),
+    file_rotated: false,
+    ring_buffer: VecDeque::new(),
+    ring_buffer_size: 0,
+}));
+
+impl Logger {
+    #[allow(clippy::mut_from_ref)]
+    fn state(&self) -> &mut LoggerState {
+        // SAFETY: The state is never borrowed across calls.
+        unsafe { self.0.get_mut() }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "[{:>5}]: {:>12}@{:03}: {}\n",
+            record.level(),
+            record.file().unwrap_or("<unknown>"),
+            record.line().unwrap_or(0),
+            record.args()
+        );
+
+        let state = self.state();
+        let destinations = state
+            .destinations
+            .as_deref()
+            .unwrap_or(&[LogDestination::Console]);
+        for destination in destinations {
+            match destination {
+                LogDestination::Console => {
+                    let mut st = uefi::helpers::system_table();
+                    let _ = st.stdout().write_str(&line);
+                }
+                LogDestination::Serial => {
+                    if let Some(handle) = state.serial {
+                        write_serial(handle, &line);
+                    }
+                }
+                LogDestination::File => state.file_buffer.push_str(&line),
+            }
+        }
+
+        state.ring_buffer_size += line.len();
+        state.ring_buffer.push_back(line);
+        while state.ring_buffer_size > LOG_BUFFER_SIZE {
+            match state.ring_buffer.pop_front() {
+                Some(oldest) => state.ring_buffer_size -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn write_serial(handle: Handle, line: &str) {
+    let st = uefi::helpers::system_table();
+    let bs = st.boot_services();
+    let serial = unsafe {
+        bs.open_protocol::<Serial>(
+            OpenProtocolParams {
+                handle,
+                agent: bs.image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    };
+    if let Ok(mut serial) = serial {
+        let _ = serial.write(line.replace('\n', "\r\n").as_bytes());
+    }
+}
+
+/// Installs the logger, logging to the console until configured.
+pub fn init() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Routes the logs to the given destinations.
+pub fn set_destinations(bs: &BootServices, destinations: &[LogDestination]) {
+    let serial = if destinations.contains(&LogDestination::Serial) {
+        bs.get_handle_for_protocol::<Serial>()
+            .inspect_err(|_| log::warn!("No serial port to log to."))
+            .ok()
+    } else {
+        None
+    };
+    // NOTE: The state is not borrowed while logging.
+    let state = LOGGER.state();
+    state.serial = serial;
+    state.destinations = Some(destinations.to_vec());
+}
+
+/// Writes the buffered logs to the log file, keeping the previous one.
+///
+/// Can be called repeatedly, the log file then holds all the logs so far.
+pub fn flush_log_file(bs: &BootServices) {
+    if LOGGER.state().file_buffer.is_empty() {
+        return;
+    }
+    let log_file = config().log_file.as_str();
+    let (Ok(path), Ok(old_path)) = (
+        CString16::try_from(log_file),
+        CString16::try_from(format!("{log_file}.old").as_str()),
+    ) else {
+        return;
+    };
+
+    let Some(volume) = selected_volumes(bs, &config().volumes).into_iter().next() else {
+        return;
+    };
+    let Ok(mut fs) = volume.file_system(bs) else {
+        return;
+    };
+    let state = LOGGER.state();
+    if !state.file_rotated && fs.try_exists(Path::new(&path)).unwrap_or(false) {
+        let _ = fs.rename(Path::new(&path), Path::new(&old_path));
+    }
+    state.file_rotated = true;
+    let result = fs.write(PathBuf::from(path), state.file_buffer.as_bytes());
+    // NOTE: The state is not borrowed while logging.
+    if let Err(err) = result {
+        log::warn!("Could not write the log file ({err})");
+    }
+}
+
+/// Publishes the log records kept in memory as the `FdtshimLog` EFI variable.
+///
+/// The oldest records are dropped until the firmware accepts the variable.
+pub fn publish_log(st: &SystemTable<Boot>) {
+    let log: String = LOGGER
+        .state()
+        .ring_buffer
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let mut log = log.as_bytes();
+    loop {
+        match set_fdtshim_variable_bytes(st, cstr16!("FdtshimLog"), log) {
+            Ok(_) => break,
+            Err(err) if log.len() > 1024 => {
+                log::debug!(
+                    "Could not publish {} bytes of log ({})",
+                    log.len(),
+                    err.status()
+                );
+                log = &log[log.len() / 2..];
+            }
+            Err(err) => {
+                log::warn!("Could not publish the log ({})", err.status());
+                break;
+            }
+        }
+    }
+}
@@ -1,152 +1,434 @@
 #![no_main]
 #![no_std]
 
+mod config;
 mod efi;
+mod global;
+mod hotkey;
+mod integrity;
+mod logger;
 mod matching;
+mod menu;
+mod options;
+mod overlay;
 mod protocols;
-pub mod smbios;
 mod utils;
+mod volumes;
+use crate::config::*;
 use crate::efi::*;
+use crate::hotkey::*;
+use crate::integrity::*;
 use crate::matching::*;
+use crate::menu::*;
+use crate::overlay::apply_overlays;
 use crate::protocols::dt_fixup::DtFixupFlags;
 use crate::utils::*;
+use fdtshim::sha256;
+use fdtshim::smbios;
 
 extern crate alloc;
 extern crate flat_device_tree as fdt;
+use alloc::format;
+use alloc::string::String;
 use alloc::string::ToString;
+use alloc::vec::Vec;
 use core::ffi::c_void;
 use log::debug;
 use log::error;
 use log::info;
 use log::warn;
+use uefi::cstr16;
 use uefi::prelude::*;
 use uefi::table::boot::MemoryType;
+use uefi::CStr16;
+use uefi::CString16;
 
-// TODO: make this a global; replace with argv[1] when present.
-pub const PREFIX: &str = r"\EFI\dtbs";
-pub const MAPPING: &str = r"mapping.dtb";
+/// Default prefix for the mapping and dtb files. See [`config::Config`].
+pub const DEFAULT_PREFIX: &str = r"\EFI\dtbs";
+/// Default mapping file.
+pub const DEFAULT_MAPPING: &str = r"mapping.dtb";
+/// Next stage used when none is configured.
+pub const DEFAULT_NEXT_STAGE: &str = r"\EFI\boot\grub.efi";
 
 #[entry]
 unsafe fn main(_image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
     uefi::helpers::init(&mut system_table).unwrap();
     uefi::allocator::init(&mut system_table);
-
-    log::set_max_level(log::LevelFilter::Info);
-    log::set_max_level(log::LevelFilter::Debug);
+    logger::init();
 
     let boot_services = system_table.boot_services();
+    let load_options = options::from_image(boot_services);
+    set_config(Config::load(&system_table, &load_options));
+    logger::set_destinations(boot_services, &config().log);
+    if let Some(level) = config().log_level {
+        log::set_max_level(level);
+    }
+    publish(
+        &system_table,
+        cstr16!("FdtshimVersion"),
+        env!("CARGO_PKG_VERSION"),
+    );
+    let hotkey = startup_hotkey(config().timeout);
+    if hotkey == Some(Hotkey::Debug) {
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+    let menu_requested = hotkey == Some(Hotkey::Menu);
+    // NOTE: The mapping path is validated with the configuration.
+    let mapping_path = path_for(boot_services, &config().mapping).unwrap_or_default();
+    // Next stage candidates as configured by the mapping file
+    let mut mapping_next_stages: Vec<String> = Vec::new();
 
     debug!("");
-    debug!("Reading {:?}", path_for(MAPPING).to_string());
+    debug!("Reading {:?}", mapping_path.to_string());
 
-    if let Ok(mapping_data) = read_file(boot_services, path_for(MAPPING)) {
+    if let Ok(mapping_data) = read_file(boot_services, mapping_path.clone()) {
         if let Ok(mapping_fdt) = fdt::Fdt::from_ptr(mapping_data.as_ptr()) {
-            match try_matching(&system_table, &mapping_fdt) {
-                // Found a device tree to apply?
-                Some(dtb_path) => {
-                    // Load the matched dtb file
-                    let dtb = read_file(boot_services, path_for(dtb_path))
-                        .expect("Could not load device-specific dtb!!");
-                    // Value for the final EFI_DT_TABLE
-                    let size = dtb.len();
-
-                    debug!("Determining required buffer size for the final FDT...");
-                    // We're using this call to get the appropriate final size of the EFI_DT_TABLE
-                    match efi_dt_fixup(
-                        &system_table,
-                        dtb.as_ptr() as *const c_void,
-                        &size,
-                        DtFixupFlags::DtApplyFixups,
-                    ) {
-                        Ok(_) => {}
-                        Err(status) => match status.status() {
-                            Status::BUFFER_TOO_SMALL => {}
-                            _ => {
-                                error!("Unexpected error attempting to apply EFI_DT_FIXUP_PROTOCOL! {status}");
-                                return Status::ABORTED;
-                            }
-                        },
-                    };
-                    debug!("    (Final FDT buffer size: {size})");
-
-                    // Copy the FDT to its final manually allocated location.
-                    let final_fdt = boot_services
-                        .allocate_pool(MemoryType::ACPI_RECLAIM, size)
-                        .expect(
-                            "Failed to allocate ACPI_RECLAIM memory ({size} bytes) for final FDT",
-                        );
-                    final_fdt.copy_from(dtb.as_ptr(), dtb.len());
-                    let final_fdt_p = final_fdt as *const c_void;
-
-                    debug!("Applying DT Fixups to new and final FDT...");
-                    match efi_dt_fixup(
-                        &system_table,
-                        final_fdt_p,
-                        &size,
-                        DtFixupFlags::DtApplyFixups | DtFixupFlags::DtReserveMemory,
-                    ) {
-                        Ok(_) => {
-                            info!("Succesfully applied fixups.")
-                        }
-                        Err(status) => {
-                            error!("Error calling EFI_DT_FIXUP_PROTOCOL ({status})");
-                            return Status::ABORTED;
-                        }
-                    };
+            // The configured level takes precedence over the mapping file.
+            if let Some(level) = mapping_fdt
+                .find_node("/fdtshim")
+                .and_then(|fdtshim| fdtshim.property("log-level"))
+                .and_then(|level| level.as_str())
+                .and_then(logger::parse_level)
+                .filter(|_| config().log_level.is_none() && hotkey != Some(Hotkey::Debug))
+            {
+                log::set_max_level(level);
+            }
+            if let Some(next_stages) = mapping_fdt
+                .find_node("/chosen")
+                .and_then(|chosen| chosen.property("fdtshim,next-stage"))
+            {
+                mapping_next_stages = next_stages
+                    .iter_str()
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            if let Some(version) = mapping_fdt
+                .find_node("/")
+                .and_then(|root| root.property("linux,kernel-version"))
+                .and_then(|version| version.as_str())
+            {
+                publish(&system_table, cstr16!("FdtshimMappingVersion"), version);
+            }
+            if hotkey == Some(Hotkey::Skip) {
+                info!("Skipping the dtb selection, as requested.");
+            } else if let Err(err) = select_dtb(
+                &system_table,
+                &mapping_fdt,
+                menu_requested,
+                &mut mapping_next_stages,
+            ) {
+                save_logs(&system_table);
+                return err.status();
+            }
+        } else {
+            error!("Could not parse {:?}.", mapping_path.to_string())
+        }
+    } else {
+        error!(
+            "Could not read {:?}, searched volumes:{}",
+            mapping_path.to_string(),
+            searched_volumes(boot_services)
+        )
+    }
 
-                    debug!("Installing new and final FDT...");
-                    match install_efi_dtb_table(&system_table, final_fdt_p) {
-                        Ok(_) => {
-                            info!("Succesfully installed new EFI_DT_TABLE.")
+    if config().explain {
+        info!("Press any key to continue...");
+        wait_for_key();
+    } else if log::max_level() == log::LevelFilter::Debug {
+        info!("[for debugging] Press any key to continue, or wait 10s...");
+        read_key_timeout(10);
+    }
+
+    // The load options take precedence over the mapping file.
+    let mut next_stages: Vec<(String, Option<CString16>)> = Vec::new();
+    if let Some(next_stage) = load_options.next_stage() {
+        let next_stage_args = load_options
+            .next_stage_args()
+            .and_then(|args| CString16::try_from(args).ok());
+        next_stages.push((next_stage.to_string(), next_stage_args));
+    }
+    next_stages.extend(mapping_next_stages.into_iter().map(|path| (path, None)));
+    if next_stages.is_empty() {
+        next_stages.push((DEFAULT_NEXT_STAGE.to_string(), None));
+    }
+
+    for (next_stage, next_stage_args) in next_stages.iter() {
+        info!("Next stage: {:?}", next_stage);
+        let Ok(path) = CString16::try_from(next_stage.as_str()) else {
+            error!("Invalid next stage path {:?}", next_stage);
+            continue;
+        };
+        // The next stage may not return, e.g. when it exits the boot services.
+        save_logs(&system_table);
+        match exec(boot_services, path, next_stage_args.as_deref()) {
+            Ok(_) => {
+                save_logs(&system_table);
+                return Status::SUCCESS;
+            }
+            Err(err) => warn!("Next stage {:?} failed ({})", next_stage, err.status()),
+        }
+    }
+
+    // Returning an error makes the boot manager try the next `Boot####` entry.
+    error!("No next stage could be started; returning to the firmware boot manager.");
+    if log::max_level() == log::LevelFilter::Debug {
+        info!("[for debugging] Press any key to continue, or wait 10s...");
+        read_key_timeout(10);
+    }
+    save_logs(&system_table);
+
+    Status::NOT_FOUND
+}
+
+/// Selects the dtb from the user's choice or matching, then installs it.
+///
+/// Device-specific next stage candidates are prepended to `next_stages`.
+/// Returns an error when the boot must not continue.
+unsafe fn select_dtb(
+    system_table: &SystemTable<Boot>,
+    mapping_fdt: &fdt::Fdt,
+    menu_requested: bool,
+    next_stages: &mut Vec<String>,
+) -> uefi::Result {
+    let boot_services = system_table.boot_services();
+    // The user's choice, short-circuiting matching
+    let dtb_override = dtb_override(system_table);
+    let mut choice = dtb_override.as_deref();
+    let mut matched_dtb = None;
+    if choice.is_none() || menu_requested {
+        matched_dtb = try_matching(system_table, mapping_fdt);
+    }
+    // The menu is shown on request, or when nothing could be matched, as configured
+    let menu_policy = config().menu;
+    if menu_requested && menu_policy == MenuPolicy::Never {
+        info!("Not showing the menu, as configured.");
+    }
+    let on_failure = choice.is_none() && matched_dtb.is_none();
+    if (menu_requested && menu_policy != MenuPolicy::Never)
+        || (on_failure && menu_policy == MenuPolicy::OnFailure)
+    {
+        let default = choice.or(matched_dtb.as_ref().map(|matched_dtb| matched_dtb.name));
+        // Only waiting indefinitely when someone asked for the menu
+        let timeout = match config().menu_timeout {
+            0 if menu_requested => None,
+            0 => Some(DEFAULT_MENU_TIMEOUT),
+            timeout => Some(timeout),
+        };
+        choice = Some(show_menu(boot_services, mapping_fdt, default, timeout));
+    }
+    match choice {
+        Some(OVERRIDE_FIRMWARE) | Some(OVERRIDE_NONE) => matched_dtb = None,
+        // Keeping the matched dtb when chosen as-is
+        Some(value) if matched_dtb.as_ref().is_some_and(|m| m.name == value) => {}
+        Some(value) => {
+            matched_dtb = override_match(boot_services, mapping_fdt, value)
+                .or(matched_dtb)
+                .or_else(|| try_matching(system_table, mapping_fdt));
+        }
+        None => {}
+    }
+    // Outcome recorded in dry-run mode
+    let dry_run_result;
+    match &matched_dtb {
+        // Found a device tree to apply?
+        Some(matched_dtb) => {
+            // Device-specific candidates are tried first
+            next_stages.splice(0..0, matched_dtb.next_stages.iter().map(|s| s.to_string()));
+            match install_matched_dtb(system_table, matched_dtb) {
+                Ok(()) => {
+                    dry_run_result = "ok";
+                    if !config().dry_run {
+                        let (st, method) = (system_table, matched_dtb.method());
+                        publish(st, cstr16!("FdtshimSelectedDtb"), matched_dtb.dtb_path);
+                        publish(st, cstr16!("FdtshimSelectedEntry"), matched_dtb.name);
+                        publish(st, cstr16!("FdtshimMatchMethod"), method);
+                    }
+                }
+                Err(err) if config().dry_run => dry_run_result = err.data(),
+                // A dtb failing the integrity verification is handled as per the policy.
+                Err(err) if err.status() == Status::CRC_ERROR => {
+                    dry_run_result = err.data();
+                    match config().integrity_policy {
+                        IntegrityPolicy::Firmware => {
+                            warn!("Keeping the firmware FDT, as per the policy.")
                         }
-                        Err(status) => {
-                            error!("Error installing new EFI_DT_TABLE ({status})");
-                            return Status::ABORTED;
+                        IntegrityPolicy::NoFdt => {
+                            warn!("Removing the firmware FDT, as per the policy.");
+                            if let Err(err) = uninstall_efi_dtb_table(system_table) {
+                                let status = err.status();
+                                error!("Error uninstalling the EFI_DT_TABLE ({status})");
+                            }
                         }
+                        IntegrityPolicy::Abort => return Err(err.status().into()),
                     }
                 }
-                None => {
-                    warn!(
-                        "No DTB could be matched from ambiant data. (This may not be a problem.)"
-                    );
+                Err(err) => return Err(err.status().into()),
+            }
+        }
+        None if choice == Some(OVERRIDE_FIRMWARE) => {
+            info!("Keeping the firmware FDT, as chosen.");
+            dry_run_result = "firmware";
+            if !config().dry_run {
+                publish(system_table, cstr16!("FdtshimMatchMethod"), "override");
+            }
+        }
+        None if choice == Some(OVERRIDE_NONE) => {
+            info!("Removing the firmware FDT, as chosen.");
+            dry_run_result = "no-fdt";
+            if !config().dry_run {
+                if let Err(err) = uninstall_efi_dtb_table(system_table) {
+                    error!("Error uninstalling the EFI_DT_TABLE ({})", err.status());
                 }
-            };
-
-            info!("");
-            info!("");
-            info!("Final state:");
-            if let Some(fdt) = get_efi_dtb_table(&system_table) {
-                let ambiant_fdt = fdt::Fdt::from_ptr(fdt as *const u8).unwrap();
-                let compatible = ambiant_fdt.root().expect("").compatible().first().unwrap();
-                let model = ambiant_fdt.root().expect("").model();
-                info!("Ambiant FDT: compatible = {compatible:?};");
-                info!("                  model = {model:?};");
-            } else {
-                info!("No ambiant FDT. (This may not be a problem.)");
+                publish(system_table, cstr16!("FdtshimMatchMethod"), "override");
             }
-            info!("");
-            info!("");
-
-            info!("NOTE: fdtshim.efi ran likely successfully to the end.");
-        } else {
-            // FIXME: const to mapping file path.
-            error!("Could not parse {:?}.", path_for(MAPPING).to_string())
         }
+        None => {
+            warn!("No DTB could be matched from ambiant data. (This may not be a problem.)");
+            dry_run_result = "no-match";
+            if !config().dry_run {
+                publish(system_table, cstr16!("FdtshimMatchMethod"), "none");
+            }
+        }
+    };
+    // Recorded as `key=value` lines, e.g. for the OS to read from efivarfs.
+    if config().dry_run {
+        let decision = match &matched_dtb {
+            Some(matched_dtb) => format!(
+                "result={dry_run_result}\nentry={}\ndtb={}\noverlays={}\nmethod={}\n",
+                matched_dtb.name,
+                matched_dtb.dtb_path,
+                matched_dtb.overlays.join(","),
+                matched_dtb.method(),
+            ),
+            None => format!("result={dry_run_result}\n"),
+        };
+        info!("Dry run: the DTB was not installed.");
+        publish(system_table, cstr16!("FdtshimDryRunResult"), &decision);
+    }
+
+    info!("");
+    info!("");
+    info!("Final state:");
+    if let Some(fdt) = get_efi_dtb_table(system_table) {
+        let ambiant_fdt = fdt::Fdt::from_ptr(fdt as *const u8).unwrap();
+        let compatible = ambiant_fdt.root().expect("").compatible().first().unwrap();
+        let model = ambiant_fdt.root().expect("").model();
+        info!("Ambiant FDT: compatible = {compatible:?};");
+        info!("                  model = {model:?};");
     } else {
-        // FIXME: const to mapping file path.
-        error!("Could not read {:?}.", path_for(MAPPING).to_string())
+        info!("No ambiant FDT. (This may not be a problem.)");
     }
+    info!("");
+    info!("");
 
-    if log::max_level() == log::LevelFilter::Debug {
-        info!("[for debugging] Stalling for 10s.");
-        boot_services.stall(10_000_000);
+    info!("NOTE: fdtshim.efi ran likely successfully to the end.");
+
+    Ok(())
+}
+
+/// Saves the logs so far, before starting the next stage or returning to the firmware.
+fn save_logs(system_table: &SystemTable<Boot>) {
+    logger::flush_log_file(system_table.boot_services());
+    logger::publish_log(system_table);
+}
+
+/// Publishes a volatile EFI variable for the OS, see [`efi::set_fdtshim_variable`].
+fn publish(system_table: &SystemTable<Boot>, name: &CStr16, value: &str) {
+    if let Err(err) = set_fdtshim_variable(system_table, name, value) {
+        warn!("Could not set EFI variable {} ({})", name, err.status());
     }
+}
 
-    // TODO: `exec` into next param and its parameters...
-    let ret = exec(boot_services, cstr16!(r"\EFI\boot\grub.efi").into());
+/// Loads the matched dtb, applies its overlays and the firmware fixups, then installs it.
+///
+/// In dry-run mode, the dtb is only loaded, validated, and the final size queried.
+/// Errors carry a short reason, recorded in dry-run mode.
+unsafe fn install_matched_dtb(
+    system_table: &SystemTable<Boot>,
+    matched_dtb: &MatchedDTB,
+) -> uefi::Result<(), &'static str> {
+    let boot_services = system_table.boot_services();
 
-    info!("Stalling for 10s.");
-    boot_services.stall(10_000_000);
+    // Load the matched dtb file
+    let Some(Ok(dtb)) =
+        path_for(boot_services, matched_dtb.dtb_path).map(|path| read_file(boot_services, path))
+    else {
+        error!(
+            "Could not read the device-specific dtb {:?}, searched volumes:{}",
+            matched_dtb.dtb_path,
+            searched_volumes(boot_services)
+        );
+        return Err(uefi::Error::new(Status::NOT_FOUND, "unreadable-dtb"));
+    };
+    if !verify_dtb(matched_dtb, &dtb) {
+        return Err(uefi::Error::new(Status::CRC_ERROR, "integrity-mismatch"));
+    }
+    // Apply the overlays, if any, before handing it to the fixup protocol
+    let dtb = apply_overlays(boot_services, dtb, &matched_dtb.overlays);
+    if let Err(err) = fdt::Fdt::new(&dtb) {
+        error!("Invalid device-specific dtb ({err:?})");
+        return Err(uefi::Error::new(Status::LOAD_ERROR, "invalid-dtb"));
+    }
+    // Value for the final EFI_DT_TABLE
+    let size = dtb.len();
+
+    debug!("Determining required buffer size for the final FDT...");
+    // We're using this call to get the appropriate final size of the EFI_DT_TABLE
+    match efi_dt_fixup(
+        system_table,
+        dtb.as_ptr() as *const c_void,
+        &size,
+        DtFixupFlags::DtApplyFixups,
+    ) {
+        Ok(_) => {}
+        Err(status) => match status.status() {
+            Status::BUFFER_TOO_SMALL => {}
+            _ => {
+                error!("Unexpected error attempting to apply EFI_DT_FIXUP_PROTOCOL! {status}");
+                return Err(uefi::Error::new(Status::ABORTED, "fixup-failed"));
+            }
+        },
+    };
+    debug!("    (Final FDT buffer size: {size})");
+
+    if config().dry_run {
+        return Ok(());
+    }
+
+    // Copy the FDT to its final manually allocated location.
+    let final_fdt = boot_services
+        .allocate_pool(MemoryType::ACPI_RECLAIM, size)
+        .expect("Failed to allocate ACPI_RECLAIM memory ({size} bytes) for final FDT");
+    final_fdt.copy_from(dtb.as_ptr(), dtb.len());
+    let final_fdt_p = final_fdt as *const c_void;
+
+    debug!("Applying DT Fixups to new and final FDT...");
+    match efi_dt_fixup(
+        system_table,
+        final_fdt_p,
+        &size,
+        DtFixupFlags::DtApplyFixups | DtFixupFlags::DtReserveMemory,
+    ) {
+        Ok(_) => {
+            info!("Succesfully applied fixups.")
+        }
+        Err(status) => {
+            error!("Error calling EFI_DT_FIXUP_PROTOCOL ({status})");
+            return Err(uefi::Error::new(Status::ABORTED, "fixup-failed"));
+        }
+    };
+
+    debug!("Installing new and final FDT...");
+    match install_efi_dtb_table(system_table, final_fdt_p) {
+        Ok(_) => {
+            info!("Succesfully installed new EFI_DT_TABLE.")
+        }
+        Err(status) => {
+            error!("Error installing new EFI_DT_TABLE ({status})");
+            return Err(uefi::Error::new(Status::ABORTED, "install-failed"));
+        }
+    }
 
-    ret
+    Ok(())
 }
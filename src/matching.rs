@@ -1,226 +1,787 @@
+use crate::config::config;
 use crate::efi::*;
 use crate::smbios::*;
+use crate::utils::file_exists;
+use crate::utils::is_valid_path;
+use crate::utils::path_for;
 use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::cmp::Reverse;
+use core::mem::offset_of;
+use flat_device_tree::node::FdtNode;
+use flat_device_tree::node::NodeProperty;
 use flat_device_tree::Fdt;
 use log::debug;
 use log::info;
 use log::warn;
+use uefi::cstr16;
 use uefi::prelude::*;
 
-struct MatchedDTB<'a> {
-    rank: usize,
-    dtb_path: &'a str,
+/// Logs a line of the `--explain` matching report, only as a debug message otherwise.
+macro_rules! explain {
+    ($($arg:tt)+) => {
+        if crate::config::config().explain {
+            info!($($arg)+)
+        } else {
+            debug!($($arg)+)
+        }
+    };
+}
+
+/// Generic compatibles, naming a SoC (or family) rather than a device, excluded from ranking.
+///
+/// Used when the mapping has no `/fdtshim/generic-compatibles` list.
+const GENERIC_COMPATIBLES: &[&str] = &[
+    "allwinner,sun4i-a10",
+    "allwinner,sun5i-a13",
+    "allwinner,sun7i-a20",
+    "allwinner,sun8i-a33",
+    "allwinner,sun8i-a83t",
+    "allwinner,sun8i-h3",
+    "allwinner,sun8i-r40",
+    "allwinner,sun8i-v3",
+    "allwinner,sun8i-v3s",
+    "allwinner,sun50i-a64",
+    "allwinner,sun50i-h5",
+    "allwinner,sun50i-h6",
+    "allwinner,sun50i-h616",
+    "amlogic,meson-gxbb",
+    "amlogic,meson-gxl",
+    "amlogic,meson-gxm",
+    "amlogic,meson-axg",
+    "amlogic,g12a",
+    "amlogic,g12b",
+    "amlogic,sm1",
+    "brcm,bcm2835",
+    "brcm,bcm2836",
+    "brcm,bcm2837",
+    "brcm,bcm2711",
+    "brcm,bcm2712",
+    "fsl,imx8mm",
+    "fsl,imx8mn",
+    "fsl,imx8mp",
+    "fsl,imx8mq",
+    "mediatek,mt8173",
+    "mediatek,mt8183",
+    "mediatek,mt8192",
+    "mediatek,mt8195",
+    "nvidia,tegra124",
+    "nvidia,tegra210",
+    "nvidia,tegra186",
+    "nvidia,tegra194",
+    "nvidia,tegra234",
+    "qcom,sc7180",
+    "qcom,sc7280",
+    "qcom,sc8280xp",
+    "qcom,sdm845",
+    "qcom,sm8250",
+    "qcom,x1e80100",
+    "rockchip,px30",
+    "rockchip,rk3288",
+    "rockchip,rk3328",
+    "rockchip,rk3368",
+    "rockchip,rk3399",
+    "rockchip,rk3566",
+    "rockchip,rk3568",
+    "rockchip,rk3588",
+    "rockchip,rk3588s",
+    "sochip,s3",
+    "ti,am625",
+    "ti,j721e",
+];
+
+/// How well a `/mapping` node matched, compared as documented in MATCHING.md.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    /// The node's `priority`, `0` when not given.
+    pub priority: i32,
+    /// Rank of the best matched ambiant compatible, `0` being the most specific.
+    pub compatible_rank: Option<usize>,
+    /// Number of `dmi-match` fields matched, when all of them matched.
+    pub dmi_fields: Option<usize>,
+    /// Points for the compatible rank and the number of DMI fields matched.
+    pub points: usize,
+}
+
+impl Score {
+    fn new(
+        priority: i32,
+        compatible_rank: Option<usize>,
+        dmi_fields: Option<usize>,
+        ambiant_compatibles: usize,
+    ) -> Self {
+        let compatible_points = compatible_rank.map_or(0, |rank| ambiant_compatibles - rank);
+        Self {
+            priority,
+            compatible_rank,
+            dmi_fields,
+            points: compatible_points + dmi_fields.unwrap_or(0),
+        }
+    }
+
+    /// Tie-break order: priority, points, compatible rank, then DMI fields.
+    fn key(&self) -> (i32, usize, Reverse<usize>, usize) {
+        (
+            self.priority,
+            self.points,
+            Reverse(self.compatible_rank.unwrap_or(usize::MAX)),
+            self.dmi_fields.unwrap_or(0),
+        )
+    }
+
+    /// The matching methods that contributed to the score.
+    pub fn method(&self) -> &'static str {
+        match (self.compatible_rank, self.dmi_fields) {
+            (Some(_), Some(_)) => "compatible+dmi-match",
+            (Some(_), None) => "compatible",
+            (None, Some(_)) => "dmi-match",
+            (None, None) => "none",
+        }
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
-impl MatchedDTB<'_> {
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+pub struct MatchedDTB<'a> {
+    pub score: Score,
+    /// Whether the entry was selected by the user, see [`override_match`], rather than scored.
+    pub overridden: bool,
+    /// Name of the matched `/mapping` node.
+    pub name: &'a str,
+    pub dtb_path: &'a str,
+    /// Overlays (`.dtbo`) to apply on top of the dtb, in order.
+    pub overlays: Vec<&'a str>,
+    /// Device-specific next stage candidates, in order of preference.
+    pub next_stages: Vec<&'a str>,
+    /// Expected dtb size, see [`crate::integrity`].
+    pub size: Option<usize>,
+    /// Expected dtb SHA-256 digest, see [`crate::integrity`].
+    pub sha256: Option<&'a [u8]>,
+}
+impl<'a> MatchedDTB<'a> {
     pub fn new() -> Self {
         Self {
-            rank: usize::MAX,
+            score: Score::default(),
+            overridden: false,
+            name: "",
             dtb_path: "",
+            overlays: Vec::new(),
+            next_stages: Vec::new(),
+            size: None,
+            sha256: None,
+        }
+    }
+
+    /// How the entry was selected: `override`, or the scoring methods, see [`Score::method`].
+    pub fn method(&self) -> &'static str {
+        if self.overridden {
+            "override"
+        } else {
+            self.score.method()
         }
     }
+
+    /// Saves the given `/mapping` device node as the match.
+    ///
+    /// The node must have a valid `dtb` path, see [`dtb_path`].
+    fn set_device(&mut self, device: FdtNode<'_, 'a>) {
+        self.name = device.name;
+        self.dtb_path = dtb_path(&device).unwrap_or_default();
+        self.overlays = valid_paths(&device, "overlays");
+        self.next_stages = valid_paths(&device, "fdtshim,next-stage");
+        self.size = device.property("size").and_then(|size| size.as_usize());
+        self.sha256 = device.property("sha256").map(|sha256| sha256.value);
+    }
 }
 
-pub unsafe fn try_matching<'a>(st: &SystemTable<Boot>, mapping_fdt: &'a Fdt) -> Option<&'a str> {
-    debug!("-> Attempting to match device from ambiant data...");
+/// The `dtb` path of a `/mapping` node, when present and valid.
+fn dtb_path<'a>(device: &FdtNode<'_, 'a>) -> Option<&'a str> {
+    let dtb_path = device.property("dtb").and_then(|dtb| dtb.as_str());
+    match dtb_path {
+        Some(path) if is_valid_path(path) => Some(path),
+        Some(path) => {
+            warn!(
+                "Invalid dtb path {:?} for {:?}; ignoring.",
+                path, device.name
+            );
+            None
+        }
+        None => {
+            warn!("No dtb path for {:?}; ignoring.", device.name);
+            None
+        }
+    }
+}
 
-    // An ambiant FDT compatible match is always preferred.
-    if let Some(fdt) = get_efi_dtb_table(st) {
-        let mut matched_dtb = MatchedDTB::new();
-        let ambiant_fdt = fdt::Fdt::from_ptr(fdt as *const u8).unwrap();
+/// The valid paths of a `/mapping` node's list property, warning about the invalid ones.
+fn valid_paths<'a>(device: &FdtNode<'_, 'a>, name: &str) -> Vec<&'a str> {
+    let Some(paths) = device.property(name) else {
+        return Vec::new();
+    };
+    paths
+        .iter_str()
+        .filter(|path| !path.is_empty())
+        .filter(|path| {
+            let valid = is_valid_path(path);
+            if !valid {
+                warn!("Invalid path {:?} in {:?}; ignoring.", path, device.name);
+            }
+            valid
+        })
+        .collect()
+}
 
-        let compatible = ambiant_fdt.root().expect("").compatible();
-        let ambiant_compatibles: Vec<&str> = compatible.all().collect();
-
-        if let Some(mappings) = mapping_fdt.find_node("/mapping") {
-            // For all `/mapping` nodes
-            for device in mappings.children() {
-                debug!("-- {:?}", device.name);
-                // Assuming there's a `compatible` string
-                if let Some(dtb_compatible) = device.property("compatible") {
-                    // We try to find the rank of a matched compatible
-                    if let Some(candidate_rank) =
-                        // NOTE: `find_map` returns the value of...
-                        dtb_compatible.iter_str().find_map(|dtb_compatible_string| {
-                                // ... the `position` in ambiant_compatibles of ...
-                                ambiant_compatibles
-                                    .iter()
-                                    .position(|ambiant_compatible_string| {
-                                        // ... the matched string.
-                                        *ambiant_compatible_string == dtb_compatible_string
-                                    })
-                            })
-                    {
-                        // Is this candidate better ranked?
-                        if candidate_rank < matched_dtb.rank {
-                            // Save as the match!
-                            matched_dtb.rank = candidate_rank;
-                            matched_dtb.dtb_path =
-                                device.property("dtb").unwrap().as_str().unwrap();
-                        }
-                        // We can't match anything else, so bail out...
-                        if matched_dtb.rank == 0 {
-                            break;
-                        }
-                    }
-                } else {
-                    warn!("    No compatible property for {:?}?", device.name);
-                }
+/// Formats a `major.minor` release as Linux does, absent when not implemented.
+fn release(table: &SMBiosTable, offset: usize, major: u8, minor: u8) -> String {
+    if !table.has_field(offset, 2) || (major == 0xFF && minor == 0xFF) {
+        String::new()
+    } else {
+        format!("{major}.{minor}")
+    }
+}
+
+/// Collates the DMI data, named as in `/sys/class/dmi/id/`.
+///
+/// Fields hold all their values; absent fields are a single empty string.
+fn collect_dmi(smbios: &SMBios) -> BTreeMap<&'static str, Vec<String>> {
+    let mut dmi: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    let string = |table: Option<&SMBiosTable>, number| {
+        table
+            .and_then(|table| table.get_string(number))
+            .unwrap_or("")
+            .to_string()
+    };
+
+    // Type00 data
+    let table = smbios.get_table(Type00::TYPE);
+    let bios_information = smbios.get_bios_information().unwrap_or(Type00::zeroed());
+    dmi.insert(
+        "bios_vendor",
+        vec![string(table, bios_information.bios_vendor)],
+    );
+    dmi.insert(
+        "bios_version",
+        vec![string(table, bios_information.bios_version)],
+    );
+    dmi.insert("bios_date", vec![string(table, bios_information.bios_date)]);
+    dmi.insert("bios_release", vec![String::new()]);
+    dmi.insert("ec_firmware_release", vec![String::new()]);
+    if let Some(table) = table {
+        dmi.insert(
+            "bios_release",
+            vec![release(
+                table,
+                offset_of!(Type00, bios_release_major),
+                bios_information.bios_release_major,
+                bios_information.bios_release_minor,
+            )],
+        );
+        dmi.insert(
+            "ec_firmware_release",
+            vec![release(
+                table,
+                offset_of!(Type00, ec_firmware_release_major),
+                bios_information.ec_firmware_release_major,
+                bios_information.ec_firmware_release_minor,
+            )],
+        );
+    }
+
+    // Type01 data
+    let table = smbios.get_table(Type01::TYPE);
+    let system_information = smbios.get_system_information().unwrap_or(Type01::zeroed());
+    dmi.insert(
+        "sys_vendor",
+        vec![string(table, system_information.sys_vendor)],
+    );
+    dmi.insert(
+        "product_name",
+        vec![string(table, system_information.product_name)],
+    );
+    dmi.insert(
+        "product_version",
+        vec![string(table, system_information.product_version)],
+    );
+    dmi.insert(
+        "product_sku",
+        vec![string(table, system_information.product_sku)],
+    );
+    dmi.insert(
+        "product_family",
+        vec![string(table, system_information.product_family)],
+    );
+
+    // Type02 data
+    let table = smbios.get_table(Type02::TYPE);
+    let board_information = smbios.get_board_information().unwrap_or(Type02::zeroed());
+    dmi.insert(
+        "board_vendor",
+        vec![string(table, board_information.board_vendor)],
+    );
+    dmi.insert(
+        "board_name",
+        vec![string(table, board_information.board_name)],
+    );
+    dmi.insert(
+        "board_version",
+        vec![string(table, board_information.board_version)],
+    );
+
+    // Type03 data
+    let table = smbios.get_table(Type03::TYPE);
+    let chassis_information = smbios.get_chassis_information().unwrap_or(Type03::zeroed());
+    dmi.insert(
+        "chassis_vendor",
+        vec![string(table, chassis_information.chassis_vendor)],
+    );
+    dmi.insert(
+        "chassis_version",
+        vec![string(table, chassis_information.chassis_version)],
+    );
+
+    // Type11 data
+    dmi.insert(
+        "oem_strings",
+        smbios
+            .get_all_strings(Type11::TYPE)
+            .map(String::from)
+            .collect(),
+    );
+
+    // Type12 data
+    dmi.insert(
+        "system_config_options",
+        smbios
+            .get_all_strings(Type12::TYPE)
+            .map(String::from)
+            .collect(),
+    );
+
+    dmi
+}
+
+bitflags::bitflags! {
+    /// How the values of a `dmi-match` property are compared, from its `NAME-flags` property.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    struct MatchFlags: u8 {
+        /// `*` matches any sequence of characters, `?` any single character.
+        const Glob = 1;
+        /// Case-insensitive comparison.
+        const NoCase = 2;
+        /// Leading and trailing whitespace is ignored.
+        const Trim = 4;
+    }
+}
+
+/// Suffix of the properties holding the flags for a `dmi-match` property.
+const FLAGS_SUFFIX: &str = "-flags";
+
+/// Reads the flags for the `name` property of a `dmi-match` node.
+fn match_flags(dmi_match: &FdtNode, name: &str) -> MatchFlags {
+    let mut flags = MatchFlags::empty();
+    if let Some(property) = dmi_match.property(&format!("{name}{FLAGS_SUFFIX}")) {
+        for flag in property.iter_str() {
+            match flag {
+                "glob" => flags |= MatchFlags::Glob,
+                "nocase" => flags |= MatchFlags::NoCase,
+                "trim" => flags |= MatchFlags::Trim,
+                _ => warn!("    Unknown flag {:?} for {:?}", flag, name),
             }
         }
+    }
+
+    flags
+}
+
+/// Matches `value` against a glob `pattern`, backtracking to the last `*` on mismatch.
+fn glob_matches(pattern: &[char], value: &[char]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    v = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
 
-        if matched_dtb.rank != usize::MAX {
-            info!("");
-            info!("Found a `compatible`-based match:");
-            info!("    This device matches DTB path: {}", matched_dtb.dtb_path);
-            info!("");
+/// Compares a DMI value against a value from the mapping.
+fn value_matches(flags: MatchFlags, dmi_value: &str, map_value: &str) -> bool {
+    let (mut dmi_value, mut map_value) = (dmi_value, map_value);
+    if flags.contains(MatchFlags::Trim) {
+        dmi_value = dmi_value.trim();
+        map_value = map_value.trim();
+    }
+    let (dmi_value, map_value) = if flags.contains(MatchFlags::NoCase) {
+        (dmi_value.to_lowercase(), map_value.to_lowercase())
+    } else {
+        (dmi_value.to_string(), map_value.to_string())
+    };
+
+    if flags.contains(MatchFlags::Glob) {
+        let pattern: Vec<char> = map_value.chars().collect();
+        let value: Vec<char> = dmi_value.chars().collect();
+        glob_matches(&pattern, &value)
+    } else {
+        dmi_value == map_value
+    }
+}
+
+/// Compares a `dmi-match` or `dmi-exclude` property against the collated DMI data.
+///
+/// Matches when any of the DMI values matches any of the listed values.
+/// Returns `None` for fields not part of the DMI data.
+fn field_matches(
+    dmi: &BTreeMap<&'static str, Vec<String>>,
+    node: &FdtNode,
+    field: &NodeProperty,
+) -> Option<bool> {
+    let flags = match_flags(node, field.name);
+    let map_values: Vec<&str> = field.iter_str().collect();
+    let Some(dmi_values) = dmi.get(field.name) else {
+        explain!(
+            "    {} {:?}: not a DMI field, ignored",
+            node.name,
+            field.name
+        );
+        return None;
+    };
+
+    let matched = map_values.iter().any(|map_value| {
+        dmi_values
+            .iter()
+            .any(|dmi_value| value_matches(flags, dmi_value, map_value))
+    });
+    explain!(
+        "    {} {:?} {:?}: {:?} against DMI {:?}: {}",
+        node.name,
+        field.name,
+        flags,
+        map_values,
+        dmi_values,
+        if matched { "matched" } else { "did not match" }
+    );
+
+    Some(matched)
+}
+
+/// Gets the SMBIOS data, preferring the SMBIOS3 entry point.
+///
+/// Invalid SMBIOS data is logged and ignored.
+unsafe fn get_smbios(st: &SystemTable<Boot>) -> Option<SMBios<'static>> {
+    get_efi_smbios3_table(st)
+        .and_then(|smbios| {
+            SMBios::from_smbios3_ptr(smbios as *const u8)
+                .inspect_err(|err| warn!("Ignoring invalid SMBIOS3 data: {err}"))
+                .ok()
+        })
+        .or_else(|| {
+            let smbios = get_efi_smbios_table(st)?;
+            debug!("    (No usable SMBIOS3 table, using the 32-bit entry point.)");
+            SMBios::from_smbios2_ptr(smbios as *const u8)
+                .inspect_err(|err| warn!("Ignoring invalid SMBIOS data: {err}"))
+                .ok()
+        })
+}
 
-            return Some(matched_dtb.dtb_path);
+/// Evaluates the `dmi-match` node of a device.
+///
+/// Returns the number of fields matched, or `None` when there is no `dmi-match`
+/// node, or any of its fields does not match.
+fn dmi_matched_fields(
+    dmi: &BTreeMap<&'static str, Vec<String>>,
+    device: &FdtNode,
+) -> Option<usize> {
+    let dmi_match = device.children().find(|node| node.name == "dmi-match")?;
+    let mut matched_fields = 0;
+    for field in dmi_match.properties() {
+        if field.name.ends_with(FLAGS_SUFFIX) {
+            continue;
+        }
+        match field_matches(dmi, &dmi_match, &field) {
+            Some(true) => matched_fields += 1,
+            Some(false) => return None,
+            None => {}
         }
     }
 
-    // Falling back to DMI data
-    if let Some(smbios) = get_efi_smbios3_table(st) {
-        let smbios = SMBios3::from_ptr(smbios as *const u8).unwrap();
+    Some(matched_fields)
+}
+
+/// Whether any field of the `dmi-exclude` node of a device matches.
+fn dmi_excluded(dmi: &BTreeMap<&'static str, Vec<String>>, device: &FdtNode) -> bool {
+    let Some(dmi_exclude) = device.children().find(|node| node.name == "dmi-exclude") else {
+        return false;
+    };
+    for field in dmi_exclude.properties() {
+        if field.name.ends_with(FLAGS_SUFFIX) {
+            continue;
+        }
+        if field_matches(dmi, &dmi_exclude, &field) == Some(true) {
+            explain!("    Rejected: excluded by `dmi-exclude` {:?}.", field.name);
+            return true;
+        }
+    }
 
-        //
-        // First, we collate data to compare against in a map.
-        //
+    false
+}
 
-        let mut dmi: BTreeMap<&str, &str> = BTreeMap::new();
+/// Override keeping the firmware FDT, if any.
+pub const OVERRIDE_FIRMWARE: &str = "@firmware";
+/// Override removing the firmware FDT, if any.
+pub const OVERRIDE_NONE: &str = "@none";
 
-        // Type01 data
-        dmi.insert("sys_vendor", "");
-        dmi.insert("product_name", "");
-        dmi.insert("product_version", "");
-        dmi.insert("product_sku", "");
-        dmi.insert("product_family", "");
-        if let Some(system_information) = smbios.get_system_information() {
-            if let Some(table) = smbios.get_table(1) {
-                dmi.insert(
-                    "sys_vendor",
-                    table
-                        .get_string(system_information.sys_vendor)
-                        .unwrap_or(""),
-                );
-                dmi.insert(
-                    "product_name",
-                    table
-                        .get_string(system_information.product_name)
-                        .unwrap_or(""),
-                );
-                dmi.insert(
-                    "product_version",
-                    table
-                        .get_string(system_information.product_version)
-                        .unwrap_or(""),
-                );
-                dmi.insert(
-                    "product_sku",
-                    table
-                        .get_string(system_information.product_sku)
-                        .unwrap_or(""),
-                );
-                dmi.insert(
-                    "product_family",
-                    table
-                        .get_string(system_information.product_family)
-                        .unwrap_or(""),
-                );
+/// Reads the user's dtb override, the one-shot override taking precedence.
+///
+/// Besides a dtb, see [`override_match`], the override can be [`OVERRIDE_FIRMWARE`]
+/// or [`OVERRIDE_NONE`].
+/// The one-shot override is deleted once read, unless in dry-run mode.
+pub fn dtb_override(st: &SystemTable<Boot>) -> Option<String> {
+    let one_shot = cstr16!("FdtshimOverrideOneShot");
+    if let Some(value) = get_fdtshim_string_variable(st, one_shot) {
+        if !config().dry_run {
+            if let Err(err) = delete_fdtshim_variable(st, one_shot) {
+                warn!("Could not delete the one-shot override ({})", err.status());
             }
         }
+        return Some(value);
+    }
 
-        // Type02 data
-        dmi.insert("board_vendor", "");
-        dmi.insert("board_name", "");
-        dmi.insert("board_version", "");
-        if let Some(board_information) = smbios.get_board_information() {
-            if let Some(table) = smbios.get_table(2) {
-                dmi.insert(
-                    "board_vendor",
-                    table
-                        .get_string(board_information.board_vendor)
-                        .unwrap_or(""),
-                );
-                dmi.insert(
-                    "board_name",
-                    table.get_string(board_information.board_name).unwrap_or(""),
-                );
-                dmi.insert(
-                    "board_version",
-                    table
-                        .get_string(board_information.board_version)
-                        .unwrap_or(""),
-                );
+    get_fdtshim_string_variable(st, cstr16!("FdtshimOverride"))
+}
+
+/// Selects the dtb named by the user's override or menu choice, short-circuiting [`try_matching`].
+///
+/// The override names either a `/mapping` node, or a dtb path resolved as in the mapping.
+/// Returns `None`, to fall back to matching, when it names neither.
+pub fn override_match<'a>(
+    bs: &BootServices,
+    mapping_fdt: &Fdt<'a>,
+    value: &'a str,
+) -> Option<MatchedDTB<'a>> {
+    info!("Using the user's dtb choice {:?}", value);
+    let mut matched_dtb = MatchedDTB::new();
+    matched_dtb.overridden = true;
+
+    if let Some(device) = mapping_fdt
+        .find_node("/mapping")
+        .and_then(|mappings| mappings.children().find(|device| device.name == value))
+    {
+        dtb_path(&device)?;
+        matched_dtb.set_device(device);
+    } else if path_for(bs, value).is_some_and(|path| file_exists(bs, &path)) {
+        matched_dtb.name = value;
+        matched_dtb.dtb_path = value;
+    } else {
+        warn!(
+            "The dtb override {:?} names neither a mapping entry nor a dtb; ignoring.",
+            value
+        );
+        return None;
+    }
+
+    Some(matched_dtb)
+}
+
+pub unsafe fn try_matching<'a>(
+    st: &SystemTable<Boot>,
+    mapping_fdt: &'a Fdt,
+) -> Option<MatchedDTB<'a>> {
+    debug!("-> Attempting to match device from ambiant data...");
+    explain!("");
+    explain!("==== Matching report ====");
+
+    //
+    // First, we collate the ambiant data to compare against.
+    //
+
+    // Ambiant FDT compatibles, in order of priority.
+    let mut ambiant_compatibles: Vec<&str> = Vec::new();
+    if let Some(fdt) = get_efi_dtb_table(st) {
+        let ambiant_fdt = fdt::Fdt::from_ptr(fdt as *const u8).unwrap();
+
+        // Generic compatibles would match too eagerly, they are not ranked.
+        let generic_compatibles: Vec<&str> = match mapping_fdt
+            .find_node("/fdtshim")
+            .and_then(|fdtshim| fdtshim.property("generic-compatibles"))
+        {
+            Some(generic_compatibles) => generic_compatibles.iter_str().collect(),
+            None => GENERIC_COMPATIBLES.to_vec(),
+        };
+
+        let compatible = ambiant_fdt.root().expect("").compatible();
+        ambiant_compatibles = compatible
+            .all()
+            .filter(|compatible| {
+                let generic = generic_compatibles.contains(compatible);
+                if generic {
+                    explain!("Ignoring generic ambiant compatible {:?}", compatible);
+                }
+                !generic
+            })
+            .collect();
+        explain!("Ambiant compatibles, by rank: {:?}", ambiant_compatibles);
+    } else {
+        explain!("No ambiant FDT; no `compatible` matching.");
+    }
+
+    // DMI data, named as in `/sys/class/dmi/id/`.
+    let dmi = get_smbios(st).map(|smbios| {
+        debug!("SMBIOS structures:");
+        for table in smbios.tables() {
+            let (table_type, handle) = (table.header.r#type, table.header.handle);
+            debug!(
+                "    type {table_type:3} handle {handle:#06x} ({} strings)",
+                table.strings.len() - 1
+            );
+        }
+        let dmi = collect_dmi(&smbios);
+        explain!("DMI information:");
+        for (field, values) in dmi.iter() {
+            explain!("    {field} = {values:?}");
+        }
+
+        dmi
+    });
+    if dmi.is_none() {
+        explain!("No SMBIOS data; no `dmi-match` matching.");
+    }
+
+    //
+    // Then, every `/mapping` node matching either is scored, and the best scoring one wins.
+    // See MATCHING.md for the scoring rules.
+    //
+
+    let Some(mappings) = mapping_fdt.find_node("/mapping") else {
+        warn!("No `/mapping` node in mapping dtb. (This might be a problem...)");
+        return None;
+    };
+
+    let mut matched_dtb: Option<MatchedDTB> = None;
+    for device in mappings.children() {
+        explain!("-- {:?}", device.name);
+
+        // The rank of a matched compatible, 0 being the most specific.
+        let compatible_rank = device.property("compatible").and_then(|dtb_compatible| {
+            // NOTE: `filter_map` collects the value of...
+            dtb_compatible
+                .iter_str()
+                .filter_map(|dtb_compatible_string| {
+                    // ... the `position` in ambiant_compatibles of ...
+                    ambiant_compatibles
+                        .iter()
+                        .position(|ambiant_compatible_string| {
+                            // ... the matched string.
+                            *ambiant_compatible_string == dtb_compatible_string
+                        })
+                })
+                .min()
+        });
+        if let Some(dtb_compatible) = device.property("compatible") {
+            let dtb_compatibles: Vec<&str> = dtb_compatible.iter_str().collect();
+            match compatible_rank {
+                Some(rank) => explain!("    compatible {:?}: rank {rank}", dtb_compatibles),
+                None => explain!("    compatible {:?}: did not match", dtb_compatibles),
             }
         }
+        let dmi_fields = dmi
+            .as_ref()
+            .and_then(|dmi| dmi_matched_fields(dmi, &device));
 
-        // Type03 data
-        dmi.insert("chassis_vendor", "");
-        dmi.insert("chassis_version", "");
-        if let Some(chassis_information) = smbios.get_chassis_information() {
-            if let Some(table) = smbios.get_table(3) {
-                dmi.insert(
-                    "chassis_vendor",
-                    table
-                        .get_string(chassis_information.chassis_vendor)
-                        .unwrap_or(""),
-                );
-                dmi.insert(
-                    "chassis_version",
-                    table
-                        .get_string(chassis_information.chassis_version)
-                        .unwrap_or(""),
+        if compatible_rank.is_none() && dmi_fields.is_none() {
+            if device.property("compatible").is_none()
+                && !device.children().any(|node| node.name == "dmi-match")
+            {
+                warn!(
+                    "    No compatible property or dmi-match node for {:?}?",
+                    device.name
                 );
             }
+            explain!("    Rejected: matched neither `compatible` nor `dmi-match`.");
+            continue;
+        }
+        if dmi.as_ref().is_some_and(|dmi| dmi_excluded(dmi, &device)) {
+            continue;
+        }
+        if dtb_path(&device).is_none() {
+            explain!("    Rejected: no valid `dtb` path.");
+            continue;
         }
 
-        debug!("DMI information to check:\n{:?}", dmi);
-
-        //
-        // Then, loop on all nodes with `dmi-match`, and if **all** fields of the node match
-        // against the collated information, that's our match.
-        //
-
-        if let Some(mappings) = mapping_fdt.find_node("/mapping") {
-            for device in mappings.children() {
-                debug!("-- {:?}", device.name);
-                if let Some(dmi_match) = device.children().find(|node| node.name == "dmi-match") {
-                    let mut valid = true;
-                    for field in dmi_match.properties() {
-                        debug!("---- {:?}", field.name);
-                        debug!("     MAP: {:?}", field.as_str().unwrap_or("<invalid>"));
-                        // Print all values of the prop
-                        if let Some(dmi_value) = dmi.get(field.name) {
-                            debug!("     DMI: {:?}", dmi_value);
-                            if field.iter_str().all(|map_value| {
-                                debug!("     MAP: {:?}", map_value);
-                                *dmi_value != map_value
-                            }) {
-                                debug!("       DID NOT MATCH!");
-                                valid = false;
-                                break;
-                            }
-                        }
-                    }
-                    if valid {
-                        let dtb_path = device.property("dtb").unwrap().as_str().unwrap();
-                        info!("");
-                        info!("Found a `dmi-match`-based match:");
-                        info!("    This device matches DTB path: {}", dtb_path);
-                        info!("");
-
-                        return Some(dtb_path);
-                    }
-                }
-            }
-            None
-        } else {
-            warn!("No `/mapping` node in mapping dtb. (This might be a problem...)");
-            None
+        let priority = device
+            .property("priority")
+            .and_then(|priority| priority.as_usize())
+            .map(|priority| priority as u32 as i32)
+            .unwrap_or(0);
+        let score = Score::new(
+            priority,
+            compatible_rank,
+            dmi_fields,
+            ambiant_compatibles.len(),
+        );
+        explain!("    Candidate: {:?}", score);
+
+        // Is this candidate better scored? On ties, the first one is kept.
+        if matched_dtb
+            .as_ref()
+            .map_or(true, |matched_dtb| score > matched_dtb.score)
+        {
+            explain!("    Best candidate so far.");
+            let mut candidate = MatchedDTB::new();
+            candidate.score = score;
+            candidate.set_device(device);
+            matched_dtb = Some(candidate);
         }
-    } else {
-        // Nothing could be matched... oh well...
-        None
     }
+
+    match &matched_dtb {
+        Some(matched_dtb) => explain!(
+            "Decision: {:?}, matched by `{}`, {:?}",
+            matched_dtb.name,
+            matched_dtb.score.method(),
+            matched_dtb.score
+        ),
+        None => explain!("Decision: no mapping entry matched."),
+    }
+    explain!("=========================");
+
+    if let Some(matched_dtb) = &matched_dtb {
+        info!("");
+        info!("Found a `{}`-based match:", matched_dtb.score.method());
+        info!("    This device matches DTB path: {}", matched_dtb.dtb_path);
+        info!("");
+    }
+
+    matched_dtb
 }
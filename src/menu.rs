@@ -0,0 +1,188 @@
+//! Interactive dtb selection menu on the UEFI console.
+//!
+//! The menu lists all `/mapping` entries, and the choices to keep the firmware FDT,
+//! or to use no FDT at all. It is navigated with the arrow keys, `Enter` selects,
+//! `Esc` selects the default, and `P` toggles remembering the choice for the next boots,
+//! see [`crate::matching::dtb_override`]. In dry-run mode, the choice is only logged.
+//!
+//! When the menu is shown depends on the `menu` configuration, see [`MenuPolicy`].
+
+use crate::config::config;
+use crate::efi::*;
+use crate::matching::OVERRIDE_FIRMWARE;
+use crate::matching::OVERRIDE_NONE;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use flat_device_tree::Fdt;
+use log::debug;
+use log::info;
+use log::warn;
+use uefi::cstr16;
+use uefi::prelude::*;
+use uefi::proto::console::text::Color;
+use uefi::proto::console::text::Key;
+use uefi::proto::console::text::ScanCode;
+use uefi::table::boot::EventType;
+use uefi::table::boot::TimerTrigger;
+use uefi::table::boot::Tpl;
+
+/// When the menu is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MenuPolicy {
+    /// Never, even with the hotkey.
+    Never,
+    /// Only with the startup hotkey.
+    Hotkey,
+    /// With the startup hotkey, or when nothing could be matched, without an override (default).
+    #[default]
+    OnFailure,
+}
+
+impl MenuPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "never" => Some(Self::Never),
+            "hotkey" => Some(Self::Hotkey),
+            "on-failure" => Some(Self::OnFailure),
+            _ => None,
+        }
+    }
+}
+
+/// A menu item, as `(label, choice)`.
+///
+/// Choices are as for [`crate::matching::dtb_override`].
+type MenuItem<'a> = (String, &'a str);
+
+/// Lists the menu items.
+fn menu_items<'a>(mapping_fdt: &Fdt<'a>) -> Vec<MenuItem<'a>> {
+    let mut items = Vec::new();
+    if let Some(mappings) = mapping_fdt.find_node("/mapping") {
+        for device in mappings.children() {
+            let description = device
+                .property("model")
+                .or(device.property("compatible"))
+                .and_then(|property| property.as_str())
+                .unwrap_or("");
+            items.push((format!("{}  ({})", device.name, description), device.name));
+        }
+    }
+    items.push((String::from("Keep the firmware FDT"), OVERRIDE_FIRMWARE));
+    items.push((String::from("No FDT"), OVERRIDE_NONE));
+
+    items
+}
+
+/// Draws the menu, the cursor on `selected`.
+fn draw(items: &[MenuItem], selected: usize, persist: bool, remaining: Option<usize>) {
+    let mut st = uefi::helpers::system_table();
+    let stdout = st.stdout();
+    let _ = stdout.clear();
+    let _ = writeln!(stdout, "fdtshim: select the device tree to use\n");
+    for (i, (label, _)) in items.iter().enumerate() {
+        if i == selected {
+            let _ = stdout.set_color(Color::Black, Color::LightGray);
+            let _ = write!(stdout, "  > {label}");
+            let _ = stdout.set_color(Color::LightGray, Color::Black);
+            let _ = writeln!(stdout);
+        } else {
+            let _ = writeln!(stdout, "    {label}");
+        }
+    }
+    let _ = writeln!(
+        stdout,
+        "\nUp/Down: move, Enter: select, Esc: default, P: remember the choice [{}]",
+        if persist { "x" } else { " " }
+    );
+    if let Some(remaining) = remaining {
+        let _ = writeln!(stdout, "Selecting the default in {remaining}s...");
+    }
+}
+
+/// Shows the menu, and returns the user's choice, as for [`crate::matching::dtb_override`].
+///
+/// `default` is selected after `timeout` seconds, or when `Esc` is pressed.
+/// Without a default, keeping the firmware FDT is the default.
+/// Without a timeout, the menu waits indefinitely.
+pub fn show_menu<'a>(
+    bs: &BootServices,
+    mapping_fdt: &Fdt<'a>,
+    default: Option<&'a str>,
+    timeout: Option<usize>,
+) -> &'a str {
+    debug!("-> Showing the dtb selection menu...");
+    let items = menu_items(mapping_fdt);
+    let default = items
+        .iter()
+        .position(|(_, choice)| Some(*choice) == default)
+        .unwrap_or(items.len() - 2);
+
+    let mut st = uefi::helpers::system_table();
+    let _ = st.stdin().reset(false);
+    let Some(key_event) = st.stdin().wait_for_key_event() else {
+        warn!("No console input; using the default choice.");
+        return items[default].1;
+    };
+
+    // Counting down the timeout, until a key is pressed
+    let timer = unsafe { bs.create_event(EventType::TIMER, Tpl::CALLBACK, None, None) }
+        .ok()
+        .filter(|timer| {
+            bs.set_timer(timer, TimerTrigger::Periodic(10_000_000))
+                .is_ok()
+        });
+    let mut remaining = timeout.filter(|_| timer.is_some());
+
+    let (mut selected, mut persist) = (default, false);
+    let choice = loop {
+        draw(&items, selected, persist, remaining);
+
+        let mut events = vec![unsafe { key_event.unsafe_clone() }];
+        if let (Some(timer), Some(_)) = (&timer, remaining) {
+            events.push(unsafe { timer.unsafe_clone() });
+        }
+        match bs.wait_for_event(&mut events) {
+            Ok(0) => {}
+            Ok(_) => {
+                remaining = remaining.map(|remaining| remaining - 1);
+                if remaining == Some(0) {
+                    break items[default].1;
+                }
+                continue;
+            }
+            Err(_) => break items[default].1,
+        }
+
+        remaining = None;
+        match st.stdin().read_key() {
+            Ok(Some(Key::Special(ScanCode::UP))) => selected = selected.saturating_sub(1),
+            Ok(Some(Key::Special(ScanCode::DOWN))) => {
+                selected = (selected + 1).min(items.len() - 1)
+            }
+            Ok(Some(Key::Special(ScanCode::ESCAPE))) => break items[default].1,
+            Ok(Some(Key::Printable(c))) => match char::from(c) {
+                '\r' | '\n' => break items[selected].1,
+                'p' | 'P' => persist = !persist,
+                _ => {}
+            },
+            _ => {}
+        }
+    };
+    if let Some(timer) = timer {
+        let _ = bs.close_event(timer);
+    }
+    let _ = st.stdout().clear();
+
+    if persist && config().dry_run {
+        info!("Dry run: not remembering the choice, FdtshimOverride would be {choice:?}");
+    } else if persist {
+        if let Err(err) = persist_fdtshim_variable(&st, cstr16!("FdtshimOverride"), choice) {
+            warn!("Could not remember the choice ({})", err.status());
+        }
+    }
+
+    choice
+}
@@ -0,0 +1,43 @@
+//! Handling of fdtshim's own load options.
+//!
+//! The load options are handled as a command line, see [`fdtshim::load_options`].
+//! fdtshim options are:
+//!
+//!  - `--config=PATH`: configuration file to use.
+//!  - `--prefix=PATH`: prefix to search files in; can be repeated, in priority order.
+//!  - `--mapping=PATH`: mapping file to use.
+//!  - `--volume=SELECTOR`: volume to search files in; can be repeated, in priority order.
+//!  - `--explain`: report how the device was matched, and wait for a key press.
+//!  - `--dry-run`: match and validate the dtb, but do not install it.
+//!  - `--timeout=SECONDS`: seconds to wait for a startup hotkey, see [`crate::hotkey`].
+//!  - `--menu=POLICY`: `never`, `hotkey` or `on-failure`, see [`crate::menu`].
+//!  - `--menu-timeout=SECONDS`: seconds before the menu selects the default.
+//!  - `--log-level=LEVEL`: `off`, `error`, `warn`, `info`, `debug` or `trace`.
+//!  - `--log=DESTINATION`: where to log, see [`crate::logger`]; can be repeated.
+//!  - `--log-file=PATH`: log file, when logging to a file.
+//!  - `--integrity-policy=POLICY`: `firmware`, `none` or `abort`, see [`crate::integrity`].
+
+use crate::utils::image_path;
+use alloc::string::ToString;
+use log::debug;
+use uefi::prelude::*;
+use uefi::proto::loaded_image::LoadedImage;
+
+pub use fdtshim::load_options::LoadOptions;
+
+/// Reads the load options of the running image.
+pub fn from_image(bs: &BootServices) -> LoadOptions {
+    let cmdline = bs
+        .open_protocol_exclusive::<LoadedImage>(bs.image_handle())
+        .ok()
+        .and_then(|loaded_image| {
+            loaded_image
+                .load_options_as_cstr16()
+                .ok()
+                .map(|options| options.to_string())
+        })
+        .unwrap_or_default();
+    debug!("Load options: {:?}", cmdline);
+
+    LoadOptions::new(&cmdline, image_path(bs).as_deref())
+}
@@ -0,0 +1,54 @@
+//! Device tree overlay (`.dtbo`) support.
+//!
+//! The overlays are applied on the unflattened dtb, see [`fdtshim::devicetree`].
+
+use crate::utils::*;
+use alloc::vec::Vec;
+use fdtshim::devicetree::DeviceTree;
+use flat_device_tree::Fdt;
+use log::debug;
+use log::info;
+use log::warn;
+use uefi::prelude::*;
+
+/// Loads and applies the given overlays, relative to the prefixes, to the dtb.
+///
+/// Overlays that fail to load or apply are logged and skipped.
+pub fn apply_overlays(bs: &BootServices, dtb: Vec<u8>, overlays: &[&str]) -> Vec<u8> {
+    if overlays.is_empty() {
+        return dtb;
+    }
+
+    let Ok(mut tree) = DeviceTree::from_bytes(&dtb) else {
+        warn!("Could not unflatten the device-specific dtb; not applying overlays.");
+        return dtb;
+    };
+
+    for overlay_path in overlays.iter() {
+        debug!("Applying overlay {:?}...", overlay_path);
+        let Some(Ok(overlay_data)) = path_for(bs, overlay_path).map(|path| read_file(bs, path))
+        else {
+            warn!("Could not read overlay {:?}; skipping.", overlay_path);
+            continue;
+        };
+        let Ok(overlay_fdt) = Fdt::new(&overlay_data) else {
+            warn!("Could not parse overlay {:?}; skipping.", overlay_path);
+            continue;
+        };
+        let mut candidate = tree.clone();
+        match candidate.apply_overlay(&overlay_fdt) {
+            Ok(_) => {
+                info!("Applied overlay {:?}.", overlay_path);
+                tree = candidate;
+            }
+            Err(err) => {
+                warn!(
+                    "Could not apply overlay {:?} ({err}); skipping.",
+                    overlay_path
+                );
+            }
+        }
+    }
+
+    tree.to_dtb()
+}
@@ -1,27 +1,149 @@
-use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use core::ffi::c_char;
-use uefi::Error;
-use uefi::Result;
-use uefi::Status;
-use zero::{read, read_str, Pod};
+use core::fmt;
+use log::warn;
+use zero::{read, Pod};
+
+#[cfg(test)]
+mod tests;
 
 /// The type of the actual structure the header is from.
 type SMBiosTableType = u8;
 /// An indice (1-based) for a string in the table's strings.
 type SMBiosTableStringRef = u8;
 
-pub struct SMBios3<'a> {
+/// Why the SMBIOS data could not be used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SMBiosError {
+    NullPointer,
+    /// The entry point is shorter than its structure.
+    TruncatedEntryPoint,
+    /// Invalid `_SM_` or `_SM3_` anchor.
+    BadAnchor,
+    /// Invalid `_DMI_` anchor.
+    BadIntermediateAnchor,
+    BadChecksum,
+    BadIntermediateChecksum,
+    /// The entry point length field is out of range.
+    BadEntryPointLength(u8),
+    /// The structure table address is null or does not fit the address space.
+    BadTableAddress(u64),
+    /// The structure table is shorter than given by the entry point.
+    TruncatedTable,
+    /// A structure header does not fit within the structure table.
+    TruncatedStructure,
+    /// A structure formatted area is shorter than its header, or overruns the structure table.
+    BadStructureLength(u8),
+    /// A structure string set is not terminated within the structure table.
+    UnterminatedStrings,
+}
+
+impl fmt::Display for SMBiosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NullPointer => write!(f, "null entry point"),
+            Self::TruncatedEntryPoint => write!(f, "truncated entry point"),
+            Self::BadAnchor => write!(f, "bad entry point anchor"),
+            Self::BadIntermediateAnchor => write!(f, "bad intermediate anchor"),
+            Self::BadChecksum => write!(f, "bad entry point checksum"),
+            Self::BadIntermediateChecksum => write!(f, "bad intermediate checksum"),
+            Self::BadEntryPointLength(length) => {
+                write!(f, "bad entry point length {length:#x}")
+            }
+            Self::BadTableAddress(address) => write!(f, "bad structure table address {address:#x}"),
+            Self::TruncatedTable => write!(f, "truncated structure table"),
+            Self::TruncatedStructure => write!(f, "truncated structure"),
+            Self::BadStructureLength(length) => write!(f, "bad structure length {length:#x}"),
+            Self::UnterminatedStrings => write!(f, "unterminated string set"),
+        }
+    }
+}
+
+pub type SMBiosResult<T> = core::result::Result<T, SMBiosError>;
+
+fn error<T>(err: SMBiosError) -> SMBiosResult<T> {
+    Err(err)
+}
+
+/// The bytes of an entry point sum up to zero.
+fn checksum_valid(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+pub struct SMBios<'a> {
     data: &'a [u8],
-    tables: BTreeMap<SMBiosTableType, SMBiosTable<'a>>,
-    pub entry_point: &'a SMBios3EntryPoint,
+    /// All structures, in table order.
+    tables: Vec<SMBiosTable<'a>>,
+    pub entry_point: SMBiosEntryPoint<'a>,
 }
 
-unsafe fn points_to_end(ptr: *const c_char) -> bool {
-    (*(ptr as *const u8)) == 0
+/// The entry point the structure table was found from.
+pub enum SMBiosEntryPoint<'a> {
+    /// 32-bit (`_SM_`) entry point, from SMBIOS 2.1 onward.
+    V2(&'a SMBios2EntryPoint),
+    /// 64-bit (`_SM3_`) entry point, from SMBIOS 3.0 onward.
+    V3(&'a SMBios3EntryPoint),
 }
 
-#[repr(C)]
+#[repr(C, packed(1))]
+pub struct SMBios2EntryPoint {
+    /// "_SM_"; not NUL-terminated
+    pub anchor: [c_char; 4],
+    pub checksum: u8,
+    pub length: u8,
+    pub major_ver: u8,
+    pub minor_ver: u8,
+    pub max_structure_size: u16,
+    pub entry_point_rev: u8,
+    pub formatted_area: [u8; 5],
+    /// "_DMI_"; not NUL-terminated
+    pub intermediate_anchor: [c_char; 5],
+    pub intermediate_checksum: u8,
+    pub struct_table_length: u16,
+    pub struct_table_address: u32,
+    pub struct_count: u16,
+    pub bcd_rev: u8,
+}
+unsafe impl Pod for SMBios2EntryPoint {}
+impl SMBios2EntryPoint {
+    pub const ANCHOR: &'static [u8] = b"_SM_";
+    pub const INTERMEDIATE_ANCHOR: &'static [u8] = b"_DMI_";
+    /// Offset of the intermediate anchor, covered by the intermediate checksum.
+    const INTERMEDIATE_OFFSET: usize = 0x10;
+    /// Offset of the length field.
+    const LENGTH_OFFSET: usize = 0x05;
+
+    /// Validates the entry point at the start of `data`, bounded by the end of `data`.
+    pub fn parse(data: &[u8]) -> SMBiosResult<&Self> {
+        if data.len() < core::mem::size_of::<Self>() {
+            return error(SMBiosError::TruncatedEntryPoint);
+        }
+        let entry_point = read::<Self>(data);
+
+        if &data[..Self::ANCHOR.len()] != Self::ANCHOR {
+            return error(SMBiosError::BadAnchor);
+        }
+        // NOTE: Some firmwares report a length of 0x1E for the 0x1F bytes entry point.
+        let length = entry_point.length as usize;
+        if !(0x1E..=data.len()).contains(&length) {
+            return error(SMBiosError::BadEntryPointLength(entry_point.length));
+        }
+        if !checksum_valid(&data[..length]) {
+            return error(SMBiosError::BadChecksum);
+        }
+        let intermediate = &data[Self::INTERMEDIATE_OFFSET..];
+        if &intermediate[..Self::INTERMEDIATE_ANCHOR.len()] != Self::INTERMEDIATE_ANCHOR {
+            return error(SMBiosError::BadIntermediateAnchor);
+        }
+        if !checksum_valid(&intermediate[..0x0F]) {
+            return error(SMBiosError::BadIntermediateChecksum);
+        }
+
+        Ok(entry_point)
+    }
+}
+
+#[repr(C, packed(1))]
 pub struct SMBios3EntryPoint {
     /// "_SM3_"; not NUL-terminated
     pub anchor: [c_char; 5],
@@ -34,15 +156,47 @@ pub struct SMBios3EntryPoint {
     /// Must be 0
     pub reserved: u8,
     pub table_maximum_size: u32,
-    pub struct_table_address: *const u8,
+    pub struct_table_address: u64,
 }
 unsafe impl Pod for SMBios3EntryPoint {}
+impl SMBios3EntryPoint {
+    pub const ANCHOR: &'static [u8] = b"_SM3_";
+    /// Offset of the length field.
+    const LENGTH_OFFSET: usize = 0x06;
+
+    /// Validates the entry point at the start of `data`, bounded by the end of `data`.
+    pub fn parse(data: &[u8]) -> SMBiosResult<&Self> {
+        if data.len() < core::mem::size_of::<Self>() {
+            return error(SMBiosError::TruncatedEntryPoint);
+        }
+        let entry_point = read::<Self>(data);
+
+        if &data[..Self::ANCHOR.len()] != Self::ANCHOR {
+            return error(SMBiosError::BadAnchor);
+        }
+        let length = entry_point.length as usize;
+        if length < core::mem::size_of::<Self>() || length > data.len() {
+            return error(SMBiosError::BadEntryPointLength(entry_point.length));
+        }
+        if !checksum_valid(&data[..length]) {
+            return error(SMBiosError::BadChecksum);
+        }
+        if entry_point.entry_point_rev != 1 || entry_point.reserved != 0 {
+            warn!(
+                "Unexpected SMBIOS3 entry point revision {} (reserved: {})",
+                entry_point.entry_point_rev, entry_point.reserved
+            );
+        }
+
+        Ok(entry_point)
+    }
+}
 
 #[repr(C, packed(1))]
 pub struct SMBiosTableHeader {
-    r#type: SMBiosTableType,
-    length: u8,
-    handle: u16,
+    pub r#type: SMBiosTableType,
+    pub length: u8,
+    pub handle: u16,
 }
 unsafe impl Pod for SMBiosTableHeader {}
 
@@ -53,99 +207,138 @@ pub struct SMBiosTable<'a> {
     pub header: &'a SMBiosTableHeader,
     /// String set for the table
     pub strings: Vec<&'a str>,
-    /// Pointer to end of the table data (to the following table)
-    end: *const u8,
+    /// Size of the whole structure, including the string set
+    size: usize,
 }
 impl<'a> SMBiosTable<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Self> {
+    /// Parses the structure at the start of `data`, bounded by the end of `data`.
+    pub fn new(data: &'a [u8]) -> SMBiosResult<Self> {
+        if data.len() < core::mem::size_of::<SMBiosTableHeader>() {
+            return error(SMBiosError::TruncatedStructure);
+        }
         let header = read::<SMBiosTableHeader>(data);
 
+        let length = header.length as usize;
+        if length < core::mem::size_of::<SMBiosTableHeader>() || length > data.len() {
+            return error(SMBiosError::BadStructureLength(header.length));
+        }
+
         let mut strings = Vec::new();
         strings.push(""); // index 0 is not a real string...
 
-        // "Work" pointer
-        let start = data.as_ptr();
-
-        // We first skip the structured data
-        let strings_section: *const c_char =
-            unsafe { start.byte_add(header.length as usize) as *const c_char };
-
-        // Then pick out the strings from the strings section
-        let mut wip = strings_section;
-        unsafe {
-            // No strings in this table...
-            if points_to_end(wip) {
-                // Skip ahead
-                wip = wip.byte_add(1);
-            }
-            while !points_to_end(wip) {
-                let start = wip;
-                let mut length = 0;
-                while !points_to_end(wip) {
-                    length += 1;
-                    wip = wip.byte_add(1);
+        // The strings section follows the structured data
+        let strings_section = &data[length..];
+        let mut offset = 0;
+        // No strings in this table, the section is only made of two NUL bytes.
+        if strings_section.first() == Some(&0) {
+            offset += 1;
+        }
+        loop {
+            match strings_section.get(offset) {
+                None => return error(SMBiosError::UnterminatedStrings),
+                // End of the strings section
+                Some(0) => {
+                    offset += 1;
+                    break;
                 }
-                // To get the NUL byte
-                length += 1;
-                wip = wip.byte_add(1);
-                let buf = core::slice::from_raw_parts(start as *const u8, length);
-                let s = read_str(buf);
-                strings.push(s);
+                Some(_) => {}
             }
-            if !points_to_end(wip) {
-                panic!("End of strings section is unexpectedly not a NUL byte.")
-            }
-
-            // Make it point one past...
-            wip = wip.byte_add(1);
+            let Some(string_length) = strings_section[offset..].iter().position(|b| *b == 0) else {
+                return error(SMBiosError::UnterminatedStrings);
+            };
+            let string = &strings_section[offset..offset + string_length];
+            strings.push(core::str::from_utf8(string).unwrap_or(""));
+            // Skip the NUL byte
+            offset += string_length + 1;
         }
 
         Ok(Self {
-            data,
+            data: &data[..length],
             header,
             strings,
-            end: wip as *const u8,
+            size: length + offset,
         })
     }
 
-    /// # Safety
-    /// ¯\_(ツ)_/¯
-    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self> {
-        if ptr.is_null() {
-            return Err(Error::new(Status::ABORTED, ()));
+    /// Gets a string by its reference; `0` means the string is absent.
+    pub fn get_string(&self, number: SMBiosTableStringRef) -> Option<&str> {
+        if number == 0 {
+            return None;
         }
+        self.strings.get(number as usize).copied()
+    }
 
-        let header_data =
-            core::slice::from_raw_parts(ptr, core::mem::size_of::<SMBiosTableHeader>());
-
-        let header = read::<SMBiosTableHeader>(header_data);
-
-        Self::new(core::slice::from_raw_parts(ptr, header.length as usize))
+    /// Whether the formatted area includes the `size` bytes field at `offset`.
+    ///
+    /// The offset is relative to the end of the header, as with the `TypeXX` structures.
+    /// Fields added by a later SMBIOS version than the one implemented are not included.
+    pub fn has_field(&self, offset: usize, size: usize) -> bool {
+        core::mem::size_of::<SMBiosTableHeader>() + offset + size <= self.data.len()
     }
 
-    pub fn get_string(&self, number: SMBiosTableStringRef) -> Option<&str> {
-        self.strings.get(number as usize).copied()
+    /// Decodes the formatted area (after the header) as `T`.
+    ///
+    /// Fields past the end of the formatted area read as zero; see [`Self::has_field`].
+    pub fn decode<T: SMBiosStructure>(&self) -> T {
+        let formatted = &self.data[core::mem::size_of::<SMBiosTableHeader>()..];
+        let length = formatted.len().min(core::mem::size_of::<T>());
+        let mut value = core::mem::MaybeUninit::<T>::zeroed();
+        // SAFETY: `T` is plain old data, for which all-zeroes and any bytes are valid.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                formatted.as_ptr(),
+                value.as_mut_ptr() as *mut u8,
+                length,
+            );
+            value.assume_init()
+        }
     }
 }
 
-impl<'a> SMBios3<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Self> {
-        let entry_point = read::<SMBios3EntryPoint>(data);
-        // TODO: validate data (e.g. _SM3_, length, entry point revision, reserved)
-
-        let mut tables = BTreeMap::new();
-        unsafe {
-            let mut ptr = entry_point.struct_table_address;
-            loop {
-                let table = SMBiosTable::from_ptr(ptr).unwrap();
-                if table.header.r#type == Type127::TYPE {
-                    break;
-                }
-                ptr = table.end;
-                tables.insert(table.header.r#type, table);
+impl<'a> SMBios<'a> {
+    /// Collects the structures of the structure table, up to the end-of-table structure.
+    ///
+    /// The walk stops early after `max_count` structures, when given.
+    fn read_tables(
+        table: &'a [u8],
+        max_count: Option<usize>,
+    ) -> SMBiosResult<Vec<SMBiosTable<'a>>> {
+        let mut tables = Vec::new();
+        let mut offset = 0;
+        let mut count = 0;
+        while offset < table.len() && max_count.map_or(true, |max| count < max) {
+            let structure = SMBiosTable::new(&table[offset..]).inspect_err(|err| {
+                warn!("Invalid SMBIOS structure at offset {offset:#x}: {err}")
+            })?;
+            if structure.header.r#type == Type127::TYPE {
+                break;
             }
+            offset += structure.size;
+            count += 1;
+            tables.push(structure);
         }
 
+        Ok(tables)
+    }
+
+    /// Parses the structures of `table`, as given by the entry point.
+    fn with_table(
+        data: &'a [u8],
+        entry_point: SMBiosEntryPoint<'a>,
+        table: &'a [u8],
+    ) -> SMBiosResult<Self> {
+        let (length, max_count) = match entry_point {
+            SMBiosEntryPoint::V2(entry_point) => (
+                entry_point.struct_table_length as usize,
+                Some(entry_point.struct_count as usize),
+            ),
+            SMBiosEntryPoint::V3(entry_point) => (entry_point.table_maximum_size as usize, None),
+        };
+        let Some(table) = table.get(..length) else {
+            return error(SMBiosError::TruncatedTable);
+        };
+        let tables = Self::read_tables(table, max_count)?;
+
         Ok(Self {
             data,
             entry_point,
@@ -153,16 +346,86 @@ impl<'a> SMBios3<'a> {
         })
     }
 
+    /// Parses the SMBIOS3 entry point `data`, and the structure table found at its address.
+    ///
+    /// `table` must hold at least the structure table maximum size given by the entry point.
+    pub fn from_v3(data: &'a [u8], table: &'a [u8]) -> SMBiosResult<Self> {
+        let entry_point = SMBios3EntryPoint::parse(data)?;
+        Self::with_table(data, SMBiosEntryPoint::V3(entry_point), table)
+    }
+
+    /// Parses the SMBIOS entry point `data`, and the structure table found at its address.
+    ///
+    /// `table` must hold at least the structure table length given by the entry point.
+    pub fn from_v2(data: &'a [u8], table: &'a [u8]) -> SMBiosResult<Self> {
+        let entry_point = SMBios2EntryPoint::parse(data)?;
+        Self::with_table(data, SMBiosEntryPoint::V2(entry_point), table)
+    }
+
+    /// # Safety
+    /// The structure table address in `data` must be valid for its given length.
+    pub unsafe fn new_v3(data: &'a [u8]) -> SMBiosResult<Self> {
+        let entry_point = SMBios3EntryPoint::parse(data)?;
+
+        let address = entry_point.struct_table_address;
+        let table_address = match usize::try_from(address) {
+            Ok(table_address) if table_address != 0 => table_address,
+            _ => return error(SMBiosError::BadTableAddress(address)),
+        };
+        let table = core::slice::from_raw_parts(
+            table_address as *const u8,
+            entry_point.table_maximum_size as usize,
+        );
+
+        Self::with_table(data, SMBiosEntryPoint::V3(entry_point), table)
+    }
+
+    /// # Safety
+    /// The structure table address in `data` must be valid for its given length.
+    pub unsafe fn new_v2(data: &'a [u8]) -> SMBiosResult<Self> {
+        let entry_point = SMBios2EntryPoint::parse(data)?;
+
+        let address = entry_point.struct_table_address;
+        if address == 0 {
+            return error(SMBiosError::BadTableAddress(address as u64));
+        }
+        let table = core::slice::from_raw_parts(
+            address as usize as *const u8,
+            entry_point.struct_table_length as usize,
+        );
+
+        Self::with_table(data, SMBiosEntryPoint::V2(entry_point), table)
+    }
+
+    /// Reads the entry point at `ptr`, sized according to its length field.
+    unsafe fn entry_point_data<'b, T>(ptr: *const u8, length_offset: usize) -> &'b [u8] {
+        let length = *ptr.add(length_offset) as usize;
+        core::slice::from_raw_parts(ptr, length.max(core::mem::size_of::<T>()))
+    }
+
+    /// # Safety
+    /// `ptr` must point to an SMBIOS3 entry point, as installed by the firmware.
+    pub unsafe fn from_smbios3_ptr(ptr: *const u8) -> SMBiosResult<Self> {
+        if ptr.is_null() {
+            return error(SMBiosError::NullPointer);
+        }
+
+        Self::new_v3(Self::entry_point_data::<SMBios3EntryPoint>(
+            ptr,
+            SMBios3EntryPoint::LENGTH_OFFSET,
+        ))
+    }
+
     /// # Safety
-    /// ¯\_(ツ)_/¯
-    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self> {
+    /// `ptr` must point to an SMBIOS entry point, as installed by the firmware.
+    pub unsafe fn from_smbios2_ptr(ptr: *const u8) -> SMBiosResult<Self> {
         if ptr.is_null() {
-            return Err(Error::new(Status::ABORTED, ()));
+            return error(SMBiosError::NullPointer);
         }
 
-        Self::new(core::slice::from_raw_parts(
+        Self::new_v2(Self::entry_point_data::<SMBios2EntryPoint>(
             ptr,
-            core::mem::size_of::<SMBios3EntryPoint>(),
+            SMBios2EntryPoint::LENGTH_OFFSET,
         ))
     }
 
@@ -170,78 +433,68 @@ impl<'a> SMBios3<'a> {
         self.data
     }
 
-    pub fn get_table(&self, number: SMBiosTableType) -> Option<&SMBiosTable> {
-        self.tables.get(&number)
+    /// All structures, in table order.
+    pub fn tables(&self) -> impl Iterator<Item = &SMBiosTable<'a>> {
+        self.tables.iter()
     }
 
-    // Temporary until I somehow get how to do this with sum types :/
-
-    pub fn get_bios_information(&self) -> Option<&Type00> {
-        if let Some(table) = self.tables.get(&1) {
-            unsafe {
-                let table_data = core::slice::from_raw_parts(
-                    table
-                        .data
-                        .as_ptr()
-                        .byte_add(core::mem::size_of::<SMBiosTableHeader>()),
-                    core::mem::size_of::<Type00>(),
-                );
-                Some(read::<Type00>(table_data))
-            }
-        } else {
-            None
-        }
+    /// All structures of the given type, in table order.
+    pub fn get_tables(&self, number: SMBiosTableType) -> impl Iterator<Item = &SMBiosTable<'a>> {
+        self.tables
+            .iter()
+            .filter(move |table| table.header.r#type == number)
     }
 
-    pub fn get_system_information(&self) -> Option<&Type01> {
-        if let Some(table) = self.tables.get(&1) {
-            unsafe {
-                let table_data = core::slice::from_raw_parts(
-                    table
-                        .data
-                        .as_ptr()
-                        .byte_add(core::mem::size_of::<SMBiosTableHeader>()),
-                    core::mem::size_of::<Type01>(),
-                );
-                Some(read::<Type01>(table_data))
-            }
-        } else {
-            None
-        }
+    /// The first structure of the given type.
+    pub fn get_table(&self, number: SMBiosTableType) -> Option<&SMBiosTable<'a>> {
+        self.get_tables(number).next()
     }
 
-    pub fn get_board_information(&self) -> Option<&Type02> {
-        if let Some(table) = self.tables.get(&1) {
-            unsafe {
-                let table_data = core::slice::from_raw_parts(
-                    table
-                        .data
-                        .as_ptr()
-                        .byte_add(core::mem::size_of::<SMBiosTableHeader>()),
-                    core::mem::size_of::<Type02>(),
-                );
-                Some(read::<Type02>(table_data))
-            }
-        } else {
-            None
-        }
+    /// The structure with the given handle.
+    pub fn get_table_by_handle(&self, handle: u16) -> Option<&SMBiosTable<'a>> {
+        self.tables
+            .iter()
+            .find(|table| table.header.handle == handle)
     }
 
-    pub fn get_chassis_information(&self) -> Option<&Type03> {
-        if let Some(table) = self.tables.get(&1) {
-            unsafe {
-                let table_data = core::slice::from_raw_parts(
-                    table
-                        .data
-                        .as_ptr()
-                        .byte_add(core::mem::size_of::<SMBiosTableHeader>()),
-                    core::mem::size_of::<Type03>(),
-                );
-                Some(read::<Type03>(table_data))
-            }
-        } else {
-            None
-        }
+    /// Decodes the first structure of type `T`.
+    pub fn get_structure<T: SMBiosStructure>(&self) -> Option<T> {
+        self.get_table(T::TYPE).map(|table| table.decode::<T>())
+    }
+
+    pub fn get_bios_information(&self) -> Option<Type00> {
+        self.get_structure()
+    }
+
+    pub fn get_system_information(&self) -> Option<Type01> {
+        self.get_structure()
+    }
+
+    pub fn get_board_information(&self) -> Option<Type02> {
+        self.get_structure()
+    }
+
+    pub fn get_chassis_information(&self) -> Option<Type03> {
+        self.get_structure()
+    }
+
+    /// The strings of all structures of the given type, in table order.
+    ///
+    /// Useful for structures that are only made of strings, e.g. [`Type11`] and [`Type12`].
+    pub fn get_all_strings(&self, number: SMBiosTableType) -> impl Iterator<Item = &'a str> + '_ {
+        self.get_tables(number)
+            .flat_map(|table| table.strings.iter().skip(1).copied())
+    }
+}
+
+/// A structure type, decoded from the formatted area following the header.
+pub trait SMBiosStructure: Pod + Copy {
+    const TYPE: SMBiosTableType;
+
+    /// The structure with all fields absent.
+    fn zeroed() -> Self {
+        // SAFETY: plain old data, for which all-zeroes is valid.
+        unsafe { core::mem::MaybeUninit::<Self>::zeroed().assume_init() }
     }
 }
 
@@ -254,6 +507,7 @@ impl<'a> SMBios3<'a> {
 ///  - Linux: `drivers/firmware/dmi-id.c`
 ///  - Linux: `drivers/firmware/dmi_scan.c`
 ///
+#[derive(Clone, Copy)]
 #[repr(C, packed(1))]
 pub struct Type00 {
     pub bios_vendor: SMBiosTableStringRef,  // DMI_BIOS_VENDOR
@@ -270,8 +524,8 @@ pub struct Type00 {
     pub ec_firmware_release_minor: u8, // DMI_EC_FIRMWARE_RELEASE
 }
 unsafe impl Pod for Type00 {}
-impl Type00 {
-    pub const TYPE: u8 = 0;
+impl SMBiosStructure for Type00 {
+    const TYPE: SMBiosTableType = 0;
 }
 
 /// System Information
@@ -283,6 +537,7 @@ impl Type00 {
 ///  - Linux: `drivers/firmware/dmi-id.c`
 ///  - Linux: `drivers/firmware/dmi_scan.c`
 ///
+#[derive(Clone, Copy)]
 #[repr(C, packed(1))]
 pub struct Type01 {
     pub sys_vendor: SMBiosTableStringRef,      // DMI_SYS_VENDOR
@@ -295,8 +550,8 @@ pub struct Type01 {
     pub product_family: SMBiosTableStringRef, // DMI_PRODUCT_FAMILY
 }
 unsafe impl Pod for Type01 {}
-impl Type01 {
-    pub const TYPE: u8 = 1;
+impl SMBiosStructure for Type01 {
+    const TYPE: SMBiosTableType = 1;
 }
 
 /// Base board information
@@ -308,6 +563,8 @@ impl Type01 {
 ///  - Linux: `drivers/firmware/dmi-id.c`
 ///  - Linux: `drivers/firmware/dmi_scan.c`
 ///
+#[derive(Clone, Copy)]
+#[repr(C, packed(1))]
 pub struct Type02 {
     pub board_vendor: SMBiosTableStringRef,    // DMI_BOARD_VENDOR
     pub board_name: SMBiosTableStringRef,      // DMI_BOARD_NAME
@@ -316,11 +573,11 @@ pub struct Type02 {
     pub board_asset_tag: SMBiosTableStringRef, // DMI_BOARD_ASSET_TAG
 }
 unsafe impl Pod for Type02 {}
-impl Type02 {
-    pub const TYPE: u8 = 2;
+impl SMBiosStructure for Type02 {
+    const TYPE: SMBiosTableType = 2;
 }
 
-/// System Information
+/// Chassis Information
 ///
 /// Notable fields named as in /sys/class/dmi/id/
 ///
@@ -329,6 +586,7 @@ impl Type02 {
 ///  - Linux: `drivers/firmware/dmi-id.c`
 ///  - Linux: `drivers/firmware/dmi_scan.c`
 ///
+#[derive(Clone, Copy)]
 #[repr(C, packed(1))]
 pub struct Type03 {
     pub chassis_vendor: SMBiosTableStringRef, // DMI_CHASSIS_VENDOR
@@ -338,17 +596,44 @@ pub struct Type03 {
     pub chassis_asset_tag: SMBiosTableStringRef, // DMI_CHASSIS_ASSET_TAG
 }
 unsafe impl Pod for Type03 {}
-impl Type03 {
-    pub const TYPE: u8 = 3;
+impl SMBiosStructure for Type03 {
+    const TYPE: SMBiosTableType = 3;
+}
+
+/// OEM Strings
+///
+/// The strings themselves are the structure's strings.
+#[derive(Clone, Copy)]
+#[repr(C, packed(1))]
+pub struct Type11 {
+    pub count: u8,
+}
+unsafe impl Pod for Type11 {}
+impl SMBiosStructure for Type11 {
+    const TYPE: SMBiosTableType = 11;
+}
+
+/// System Configuration Options
+///
+/// The options themselves are the structure's strings.
+#[derive(Clone, Copy)]
+#[repr(C, packed(1))]
+pub struct Type12 {
+    pub count: u8,
+}
+unsafe impl Pod for Type12 {}
+impl SMBiosStructure for Type12 {
+    const TYPE: SMBiosTableType = 12;
 }
 
 /// Represents the end of the tables list.
 /// Not an actual table.
+#[derive(Clone, Copy)]
 #[repr(C, packed(1))]
 pub struct Type127 {}
 unsafe impl Pod for Type127 {}
-impl Type127 {
-    pub const TYPE: u8 = 127;
+impl SMBiosStructure for Type127 {
+    const TYPE: SMBiosTableType = 127;
 }
 
 pub enum SMBiosTableTypes {
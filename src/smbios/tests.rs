@@ -0,0 +1,304 @@
+//! Tests against SMBIOS blobs, laid out as found in memory (`/sys/firmware/dmi/tables`).
+
+use super::*;
+use alloc::vec::Vec;
+
+/// Structure table modelled on a QEMU `q35` machine, SMBIOS 3.0.
+const TABLE: &[u8] = &[
+    0x00, 0x18, 0x00, 0x00, 0x01, 0x02, 0x00, 0xe8, 0x03, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x1c, 0x00, 0x00, 0xff, 0xff, 0x53, 0x65, 0x61, 0x42, 0x49, 0x4f, 0x53, 0x00,
+    0x31, 0x2e, 0x31, 0x36, 0x2e, 0x33, 0x2d, 0x64, 0x65, 0x62, 0x69, 0x61, 0x6e, 0x2d, 0x31, 0x2e,
+    0x31, 0x36, 0x2e, 0x33, 0x2d, 0x32, 0x00, 0x30, 0x34, 0x2f, 0x30, 0x31, 0x2f, 0x32, 0x30, 0x31,
+    0x34, 0x00, 0x00, 0x01, 0x1b, 0x00, 0x01, 0x01, 0x02, 0x03, 0x00, 0x10, 0x11, 0x12, 0x13, 0x14,
+    0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x06, 0x00, 0x00, 0x51, 0x45,
+    0x4d, 0x55, 0x00, 0x53, 0x74, 0x61, 0x6e, 0x64, 0x61, 0x72, 0x64, 0x20, 0x50, 0x43, 0x20, 0x28,
+    0x51, 0x33, 0x35, 0x20, 0x2b, 0x20, 0x49, 0x43, 0x48, 0x39, 0x2c, 0x20, 0x32, 0x30, 0x30, 0x39,
+    0x29, 0x00, 0x70, 0x63, 0x2d, 0x71, 0x33, 0x35, 0x2d, 0x38, 0x2e, 0x32, 0x00, 0x00, 0x02, 0x0f,
+    0x00, 0x02, 0x01, 0x02, 0x03, 0x00, 0x00, 0x09, 0x00, 0x00, 0x03, 0x0a, 0x00, 0x51, 0x45, 0x4d,
+    0x55, 0x00, 0x53, 0x74, 0x61, 0x6e, 0x64, 0x61, 0x72, 0x64, 0x20, 0x50, 0x43, 0x00, 0x70, 0x63,
+    0x2d, 0x71, 0x33, 0x35, 0x2d, 0x38, 0x2e, 0x32, 0x00, 0x00, 0x03, 0x15, 0x00, 0x03, 0x01, 0x01,
+    0x02, 0x00, 0x00, 0x03, 0x03, 0x03, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x51,
+    0x45, 0x4d, 0x55, 0x00, 0x70, 0x63, 0x2d, 0x71, 0x33, 0x35, 0x2d, 0x38, 0x2e, 0x32, 0x00, 0x00,
+    0x0b, 0x05, 0x00, 0x0b, 0x01, 0x69, 0x6f, 0x2e, 0x73, 0x79, 0x73, 0x74, 0x65, 0x6d, 0x64, 0x2e,
+    0x63, 0x72, 0x65, 0x64, 0x65, 0x6e, 0x74, 0x69, 0x61, 0x6c, 0x3a, 0x66, 0x6f, 0x6f, 0x3d, 0x62,
+    0x61, 0x72, 0x00, 0x00, 0x7f, 0x04, 0x00, 0x7f, 0x00, 0x00,
+];
+
+/// Its SMBIOS3 entry point.
+const V3_ENTRY_POINT: &[u8] = &[
+    0x5f, 0x53, 0x4d, 0x33, 0x5f, 0xac, 0x18, 0x03, 0x00, 0x00, 0x01, 0x00, 0x0a, 0x01, 0x00, 0x00,
+    0x00, 0x60, 0xbd, 0x7f, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Its SMBIOS 2.8 entry point.
+const V2_ENTRY_POINT: &[u8] = &[
+    0x5f, 0x53, 0x4d, 0x5f, 0x2f, 0x1f, 0x02, 0x08, 0x4a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x5f, 0x44, 0x4d, 0x49, 0x5f, 0x66, 0x0a, 0x01, 0x60, 0x5a, 0x0f, 0x00, 0x06, 0x00, 0x28,
+];
+
+/// Structure table modelled on an SMBIOS 2.0 era board, with short structures.
+const OLD_TABLE: &[u8] = &[
+    0x00, 0x12, 0x00, 0x00, 0x01, 0x02, 0x00, 0xf0, 0x03, 0x0f, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x41, 0x77, 0x61, 0x72, 0x64, 0x20, 0x53, 0x6f, 0x66, 0x74, 0x77, 0x61, 0x72, 0x65,
+    0x2c, 0x20, 0x49, 0x6e, 0x63, 0x2e, 0x00, 0x41, 0x53, 0x55, 0x53, 0x20, 0x50, 0x32, 0x42, 0x2d,
+    0x46, 0x20, 0x41, 0x43, 0x50, 0x49, 0x20, 0x42, 0x49, 0x4f, 0x53, 0x20, 0x52, 0x65, 0x76, 0x69,
+    0x73, 0x69, 0x6f, 0x6e, 0x20, 0x31, 0x30, 0x30, 0x36, 0x00, 0x30, 0x31, 0x2f, 0x32, 0x35, 0x2f,
+    0x32, 0x30, 0x30, 0x30, 0x00, 0x00, 0x01, 0x08, 0x01, 0x00, 0x01, 0x02, 0x03, 0x04, 0x53, 0x79,
+    0x73, 0x74, 0x65, 0x6d, 0x20, 0x4d, 0x61, 0x6e, 0x75, 0x66, 0x61, 0x63, 0x74, 0x75, 0x72, 0x65,
+    0x72, 0x00, 0x53, 0x79, 0x73, 0x74, 0x65, 0x6d, 0x20, 0x4e, 0x61, 0x6d, 0x65, 0x00, 0x53, 0x79,
+    0x73, 0x74, 0x65, 0x6d, 0x20, 0x56, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x00, 0x53, 0x59, 0x53,
+    0x2d, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30, 0x00, 0x00, 0x02, 0x08, 0x02,
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x41, 0x53, 0x55, 0x53, 0x54, 0x65, 0x4b, 0x20, 0x43, 0x6f, 0x6d,
+    0x70, 0x75, 0x74, 0x65, 0x72, 0x20, 0x49, 0x4e, 0x43, 0x2e, 0x00, 0x50, 0x32, 0x42, 0x2d, 0x46,
+    0x00, 0x52, 0x45, 0x56, 0x20, 0x31, 0x2e, 0x78, 0x78, 0x00, 0x42, 0x52, 0x44, 0x2d, 0x30, 0x30,
+    0x30, 0x31, 0x00, 0x00, 0x03, 0x09, 0x03, 0x00, 0x01, 0x03, 0x02, 0x03, 0x00, 0x43, 0x68, 0x61,
+    0x73, 0x73, 0x69, 0x73, 0x20, 0x4d, 0x61, 0x6e, 0x75, 0x66, 0x61, 0x63, 0x74, 0x75, 0x72, 0x00,
+    0x43, 0x68, 0x61, 0x73, 0x73, 0x69, 0x73, 0x20, 0x56, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x00,
+    0x43, 0x68, 0x61, 0x73, 0x73, 0x69, 0x73, 0x20, 0x53, 0x65, 0x72, 0x69, 0x61, 0x6c, 0x00, 0x00,
+    0x7f, 0x04, 0x00, 0x7f, 0x00, 0x00,
+];
+
+/// Its SMBIOS 2.0 entry point.
+const OLD_V2_ENTRY_POINT: &[u8] = &[
+    0x5f, 0x53, 0x4d, 0x5f, 0x37, 0x1f, 0x02, 0x00, 0x4a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x5f, 0x44, 0x4d, 0x49, 0x5f, 0xbc, 0x16, 0x01, 0xc0, 0xa1, 0x0f, 0x00, 0x05, 0x00, 0x20,
+];
+
+/// Sets the checksum byte at `offset` so that `data[range]` sums up to zero.
+fn fix_checksum(data: &mut [u8], range: core::ops::Range<usize>, offset: usize) {
+    data[offset] = 0;
+    let sum = data[range]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    data[offset] = sum.wrapping_neg();
+}
+
+/// The SMBIOS3 entry point, with the given structure table maximum size.
+fn v3_entry_point(table_maximum_size: u32) -> Vec<u8> {
+    let mut entry_point = V3_ENTRY_POINT.to_vec();
+    entry_point[0x0C..0x10].copy_from_slice(&table_maximum_size.to_le_bytes());
+    fix_checksum(&mut entry_point, 0..0x18, 0x05);
+    entry_point
+}
+
+/// Length of the Type 0 structure of [`TABLE`], including its strings.
+const TYPE0_SIZE: usize = 67;
+
+#[test]
+fn valid_v3_entry_point() {
+    let smbios = SMBios::from_v3(V3_ENTRY_POINT, TABLE).unwrap();
+    assert!(
+        matches!(smbios.entry_point, SMBiosEntryPoint::V3(entry_point)
+        if entry_point.major_ver == 3 && entry_point.minor_ver == 0)
+    );
+    assert_eq!(smbios.raw_data(), V3_ENTRY_POINT);
+
+    let types: Vec<_> = smbios.tables().map(|table| table.header.r#type).collect();
+    assert_eq!(types, [0, 1, 2, 3, 11]);
+    assert_eq!(smbios.get_table_by_handle(0x0300).unwrap().header.r#type, 3);
+
+    let table = smbios.get_table(Type01::TYPE).unwrap();
+    let system = smbios.get_system_information().unwrap();
+    assert_eq!(table.get_string(system.sys_vendor), Some("QEMU"));
+    assert_eq!(
+        table.get_string(system.product_name),
+        Some("Standard PC (Q35 + ICH9, 2009)")
+    );
+    assert_eq!(table.get_string(system.product_version), Some("pc-q35-8.2"));
+    assert_eq!(table.get_string(system.product_serial), None);
+    assert_eq!({ system.product_uuid }[0], 0x10);
+    assert!(table.has_field(0x16, 1));
+    assert!(!table.has_field(0x17, 1));
+
+    let strings: Vec<_> = smbios.get_all_strings(Type11::TYPE).collect();
+    assert_eq!(strings, ["io.systemd.credential:foo=bar"]);
+}
+
+#[test]
+fn valid_v2_entry_point() {
+    let smbios = SMBios::from_v2(V2_ENTRY_POINT, TABLE).unwrap();
+    assert!(
+        matches!(smbios.entry_point, SMBiosEntryPoint::V2(entry_point)
+        if entry_point.major_ver == 2 && entry_point.minor_ver == 8)
+    );
+    assert_eq!(smbios.tables().count(), 5);
+
+    let table = smbios.get_table(Type00::TYPE).unwrap();
+    let bios = smbios.get_bios_information().unwrap();
+    assert_eq!(table.get_string(bios.bios_vendor), Some("SeaBIOS"));
+    assert_eq!(table.get_string(bios.bios_date), Some("04/01/2014"));
+    assert_eq!({ bios.bios_release_major }, 0);
+
+    let table = smbios.get_table(Type02::TYPE).unwrap();
+    let board = smbios.get_board_information().unwrap();
+    assert_eq!(table.get_string(board.board_vendor), Some("QEMU"));
+    assert_eq!(table.get_string(board.board_name), Some("Standard PC"));
+
+    let table = smbios.get_table(Type03::TYPE).unwrap();
+    let chassis = smbios.get_chassis_information().unwrap();
+    assert_eq!(
+        table.get_string(chassis.chassis_version),
+        Some("pc-q35-8.2")
+    );
+    assert_eq!({ chassis.chassis_type }, 1);
+}
+
+#[test]
+fn bad_checksum() {
+    let mut entry_point = V3_ENTRY_POINT.to_vec();
+    entry_point[0x08] ^= 1;
+    assert_eq!(
+        SMBios::from_v3(&entry_point, TABLE).err(),
+        Some(SMBiosError::BadChecksum)
+    );
+
+    let mut entry_point = V2_ENTRY_POINT.to_vec();
+    entry_point[0x07] ^= 1;
+    assert_eq!(
+        SMBios::from_v2(&entry_point, TABLE).err(),
+        Some(SMBiosError::BadChecksum)
+    );
+
+    // A valid entry point checksum, covering a corrupted intermediate area.
+    let mut entry_point = V2_ENTRY_POINT.to_vec();
+    entry_point[0x18] ^= 1;
+    fix_checksum(&mut entry_point, 0..0x1F, 0x04);
+    assert_eq!(
+        SMBios::from_v2(&entry_point, TABLE).err(),
+        Some(SMBiosError::BadIntermediateChecksum)
+    );
+}
+
+#[test]
+fn bad_anchor() {
+    let mut entry_point = V3_ENTRY_POINT.to_vec();
+    entry_point[..5].copy_from_slice(b"_SM4_");
+    assert_eq!(
+        SMBios::from_v3(&entry_point, TABLE).err(),
+        Some(SMBiosError::BadAnchor)
+    );
+    // The 32-bit entry point is long enough to be taken for a 64-bit one.
+    assert_eq!(
+        SMBios::from_v3(V2_ENTRY_POINT, TABLE).err(),
+        Some(SMBiosError::BadAnchor)
+    );
+
+    let mut entry_point = V2_ENTRY_POINT.to_vec();
+    entry_point[0x10..0x15].copy_from_slice(b"_DMX_");
+    fix_checksum(&mut entry_point, 0..0x1F, 0x04);
+    assert_eq!(
+        SMBios::from_v2(&entry_point, TABLE).err(),
+        Some(SMBiosError::BadIntermediateAnchor)
+    );
+}
+
+#[test]
+fn truncated_entry_point() {
+    assert_eq!(
+        SMBios::from_v3(&V3_ENTRY_POINT[..0x10], TABLE).err(),
+        Some(SMBiosError::TruncatedEntryPoint)
+    );
+    assert_eq!(
+        SMBios::from_v2(&V2_ENTRY_POINT[..0x1E], TABLE).err(),
+        Some(SMBiosError::TruncatedEntryPoint)
+    );
+}
+
+#[test]
+fn truncated_table() {
+    // Shorter than given by the entry point.
+    assert_eq!(
+        SMBios::from_v3(V3_ENTRY_POINT, &TABLE[..100]).err(),
+        Some(SMBiosError::TruncatedTable)
+    );
+    assert_eq!(
+        SMBios::from_v2(V2_ENTRY_POINT, &TABLE[..TABLE.len() - 1]).err(),
+        Some(SMBiosError::TruncatedTable)
+    );
+
+    // Given by the entry point as ending within the Type 1 header, then formatted area.
+    let entry_point = v3_entry_point(TYPE0_SIZE as u32 + 2);
+    assert_eq!(
+        SMBios::from_v3(&entry_point, TABLE).err(),
+        Some(SMBiosError::TruncatedStructure)
+    );
+    let entry_point = v3_entry_point(TYPE0_SIZE as u32 + 8);
+    assert_eq!(
+        SMBios::from_v3(&entry_point, TABLE).err(),
+        Some(SMBiosError::BadStructureLength(0x1B))
+    );
+}
+
+#[test]
+fn missing_double_nul() {
+    // The Type 0 string set, without its final NUL.
+    let entry_point = v3_entry_point(TYPE0_SIZE as u32 - 1);
+    assert_eq!(
+        SMBios::from_v3(&entry_point, TABLE).err(),
+        Some(SMBiosError::UnterminatedStrings)
+    );
+
+    // A structure without strings, with a single NUL.
+    assert_eq!(
+        SMBiosTable::new(&[127, 4, 0x00, 0x7F, 0]).err(),
+        Some(SMBiosError::UnterminatedStrings)
+    );
+    let table = SMBiosTable::new(&[127, 4, 0x00, 0x7F, 0, 0]).unwrap();
+    assert_eq!(table.strings, [""]);
+}
+
+#[test]
+fn short_old_version_structures() {
+    let smbios = SMBios::from_v2(OLD_V2_ENTRY_POINT, OLD_TABLE).unwrap();
+    assert_eq!(smbios.tables().count(), 4);
+
+    // SMBIOS 2.0 Type 0 stops after the BIOS characteristics.
+    let table = smbios.get_table(Type00::TYPE).unwrap();
+    let bios = smbios.get_bios_information().unwrap();
+    assert_eq!(table.data.len(), 0x12);
+    assert_eq!(
+        table.get_string(bios.bios_vendor),
+        Some("Award Software, Inc.")
+    );
+    assert_eq!({ bios.bios_characteristics }, 0x0Bu64.to_le_bytes());
+    assert!(table.has_field(6, 8));
+    assert!(!table.has_field(14, 1));
+    assert!(!table.has_field(16, 1));
+    assert_eq!({ bios.bios_characteristics_ext1 }, 0);
+    assert_eq!({ bios.bios_release_major }, 0);
+    assert_eq!({ bios.ec_firmware_release_minor }, 0);
+
+    // SMBIOS 2.0 Type 1 stops after the serial number.
+    let table = smbios.get_table(Type01::TYPE).unwrap();
+    let system = smbios.get_system_information().unwrap();
+    assert_eq!(table.data.len(), 0x08);
+    assert_eq!(
+        table.get_string(system.product_serial),
+        Some("SYS-1234567890")
+    );
+    assert!(!table.has_field(4, 16));
+    assert_eq!({ system.product_uuid }, [0; 16]);
+    assert_eq!(table.get_string(system.product_sku), None);
+    assert_eq!(table.get_string(system.product_family), None);
+
+    // Type 2 without an asset tag.
+    let table = smbios.get_table(Type02::TYPE).unwrap();
+    let board = smbios.get_board_information().unwrap();
+    assert_eq!(table.data.len(), 0x08);
+    assert_eq!(table.get_string(board.board_serial), Some("BRD-0001"));
+    assert!(!table.has_field(4, 1));
+    assert_eq!(table.get_string(board.board_asset_tag), None);
+
+    // SMBIOS 2.0 Type 3, complete as far as decoded.
+    let table = smbios.get_table(Type03::TYPE).unwrap();
+    let chassis = smbios.get_chassis_information().unwrap();
+    assert_eq!(table.data.len(), 0x09);
+    assert!(table.has_field(4, 1));
+    assert!(!table.has_field(5, 1));
+    assert_eq!({ chassis.chassis_type }, 3);
+    assert_eq!(
+        table.get_string(chassis.chassis_serial),
+        Some("Chassis Serial")
+    );
+    assert_eq!(table.get_string(chassis.chassis_asset_tag), None);
+}
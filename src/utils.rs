@@ -1,75 +1,196 @@
 //! Higher-level order helpers
 
-use crate::PREFIX;
+use crate::config::config;
+use crate::volumes::selected_volumes;
+use crate::volumes::VolumeSelector;
 
+use alloc::format;
+use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
+use core::fmt::Write;
 use log::debug;
 use log::error;
-use uefi::fs::{FileSystem, FileSystemResult, Path, PathBuf};
+use uefi::fs::{
+    Error as FileSystemError, FileSystemResult, IoError, IoErrorContext, Path, PathBuf,
+};
 use uefi::prelude::*;
 use uefi::proto::device_path::build::{self, DevicePathBuilder};
-use uefi::proto::device_path::{DevicePath, DeviceSubType, DeviceType, LoadedImageDevicePath};
-//use uefi::proto::loaded_image::LoadedImage;
-use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::proto::device_path::{
+    DevicePath, DevicePathNodeEnum, DeviceSubType, DeviceType, LoadedImageDevicePath,
+};
+use uefi::proto::loaded_image::LoadedImage;
 use uefi::table::boot::LoadImageSource;
-use uefi::table::boot::ScopedProtocol;
+use uefi::CStr16;
 use uefi::CString16;
 use uefi::Result;
 
+/// Reads a file from the first of the configured volumes it can be read from.
 // https://docs.rs/uefi/latest/uefi/fs/index.html#use-str-as-path
 pub fn read_file(bs: &BootServices, path: CString16) -> FileSystemResult<Vec<u8>> {
+    read_file_in(bs, &config().volumes, path)
+}
+
+/// Reads a file from the first of the selected volumes it can be read from.
+///
+/// Missing files are not logged as errors, as some are optional; callers needing the file
+/// report the failure, see [`searched_volumes`].
+pub fn read_file_in(
+    bs: &BootServices,
+    selectors: &[VolumeSelector],
+    path: CString16,
+) -> FileSystemResult<Vec<u8>> {
     debug!("-> read_file({path});");
-    let fs: ScopedProtocol<SimpleFileSystem> = bs.get_image_file_system(bs.image_handle()).unwrap();
-    let mut fs = FileSystem::new(fs);
-    fs.read(Path::new(&path))
+    let volumes = selected_volumes(bs, selectors);
+    let mut result = Err(FileSystemError::Io(IoError {
+        path: PathBuf::from(path.clone()),
+        context: IoErrorContext::CantOpenVolume,
+        uefi_error: Status::NOT_FOUND.into(),
+    }));
+    for volume in volumes.iter() {
+        match volume.file_system(bs) {
+            Ok(mut fs) => result = fs.read(Path::new(&path)),
+            Err(err) => debug!("    Could not open volume {volume} ({})", err.status()),
+        }
+        if result.is_ok() {
+            return result;
+        }
+    }
+
+    debug!("    Not found in any of the searched volumes.");
+    result
+}
+
+/// Lists the volumes searched by [`read_file`], one per line, for error messages.
+pub fn searched_volumes(bs: &BootServices) -> String {
+    let volumes = selected_volumes(bs, &config().volumes);
+    if volumes.is_empty() {
+        return format!("\n    (no volume matches {:?})", config().volumes);
+    }
+    volumes.iter().fold(String::new(), |mut list, volume| {
+        let _ = write!(list, "\n    {volume}");
+        list
+    })
+}
+
+/// Whether the file exists in any of the configured volumes.
+pub fn file_exists(bs: &BootServices, path: &CString16) -> bool {
+    selected_volumes(bs, &config().volumes)
+        .iter()
+        .filter_map(|volume| volume.file_system(bs).ok())
+        .any(|mut fs| fs.try_exists(Path::new(path)).unwrap_or(false))
+}
+
+/// Whether a path can be used with UEFI, i.e. is valid UCS-2.
+pub fn is_valid_path(path: &str) -> bool {
+    CString16::try_from(path).is_ok()
+}
+
+/// Resolves a path against the configured prefixes.
+///
+/// The path under the first prefix it exists in is used, defaulting to the first prefix.
+/// Absolute paths are used as-is.
+/// Returns `None` when the path is not valid UCS-2, see [`is_valid_path`].
+pub fn path_for(bs: &BootServices, path: &str) -> Option<CString16> {
+    let path = CString16::try_from(path).ok()?;
+    if path.to_string().starts_with('\\') {
+        return Some(path);
+    }
+
+    let candidates: Vec<CString16> = config()
+        .prefixes
+        .iter()
+        .filter_map(|prefix| CString16::try_from(prefix.as_str()).ok())
+        .map(|prefix| {
+            let mut p = PathBuf::from(prefix);
+            p.push(PathBuf::from(path.clone()));
+            p.to_cstr16().into()
+        })
+        .collect();
+
+    candidates
+        .iter()
+        .find(|candidate| file_exists(bs, candidate))
+        .or(candidates.first())
+        .cloned()
+        .or(Some(path))
 }
 
-// TODO: generic "join" with vec input?
-pub fn path_for(path: &str) -> CString16 {
-    let mut p = PathBuf::from(CString16::try_from(PREFIX).unwrap());
-    p.push(PathBuf::from(CString16::try_from(path).unwrap()));
-    p.to_cstr16().into()
+/// Gets the directory the running image was loaded from.
+pub fn image_directory(bs: &BootServices) -> Option<CString16> {
+    let path = image_path(bs)?;
+    let (directory, _) = path.rsplit_once('\\')?;
+    CString16::try_from(directory).ok()
+}
+
+/// Gets the path the running image was loaded from, within its volume.
+pub fn image_path(bs: &BootServices) -> Option<String> {
+    let loaded_image_device_path = bs
+        .open_protocol_exclusive::<LoadedImageDevicePath>(bs.image_handle())
+        .ok()?;
+    let mut path = PathBuf::new();
+    for node in loaded_image_device_path.node_iter() {
+        if let Ok(DevicePathNodeEnum::MediaFilePath(file_path)) = node.as_enum() {
+            path.push(PathBuf::from(file_path.path_name().to_cstring16().ok()?));
+        }
+    }
+    Some(path.to_string())
 }
 
 /// Wrapper around load_image and start_image to "simply" launch an EFI program from path.
-pub fn exec(bs: &BootServices, path: CString16) -> Status {
+///
+/// The given load options, if any, are passed as-is to the started image.
+///
+/// Returns the status of the failed call, or the exit status of the started image.
+pub fn exec(bs: &BootServices, path: CString16, load_options: Option<&CStr16>) -> Result {
     let mut storage = Vec::new();
-    if let Ok(image_path) = get_image_path_for(bs, &mut storage, &path) {
-        if let Ok(image_handle) = bs.load_image(
+    let image_path = get_image_path_for(bs, &mut storage, &path)?;
+    let image_handle = bs
+        .load_image(
             bs.image_handle(),
             LoadImageSource::FromDevicePath {
                 device_path: image_path,
                 from_boot_manager: false,
             },
-        ) {
-            // FIXME: take in params too for "execline"-like support
-            /*
-            let mut loaded_image = bs
-                .open_protocol_exclusive::<LoadedImage>(image_handle)
-                .expect("failed to open LoadedImage protocol");
-            let load_options = cstr16!(r"{path} *args"); // XXX
-            unsafe {
-                loaded_image.set_load_options(
-                    load_options.as_ptr().cast(),
-                    load_options.num_bytes() as u32,
-                );
-            }
-            */
-
-            debug!("Launching image {:?}...", path.to_string());
-            if let Err(err) = bs.start_image(image_handle) {
+        )
+        .inspect_err(|err| {
+            error!(
+                "failed to load image {:?} ({})",
+                path.to_string(),
                 err.status()
-            } else {
-                Status::LOAD_ERROR
-            }
-        } else {
-            error!("failed to load image {:?}", path.to_string());
-            Status::LOAD_ERROR
+            )
+        })?;
+
+    if let Some(load_options) = load_options {
+        debug!("    with load options {:?}", load_options.to_string());
+        let mut loaded_image = bs
+            .open_protocol_exclusive::<LoadedImage>(image_handle)
+            .inspect_err(|err| {
+                error!(
+                    "failed to open the LoadedImage protocol of {:?} ({})",
+                    path.to_string(),
+                    err.status()
+                );
+                // The image is not started; let the next candidate be tried.
+                let _ = bs.unload_image(image_handle);
+            })?;
+        // NOTE: `load_options` outlives the `start_image` call.
+        unsafe {
+            loaded_image.set_load_options(
+                load_options.as_ptr().cast(),
+                load_options.num_bytes() as u32,
+            );
         }
-    } else {
-        Status::LOAD_ERROR
     }
+
+    debug!("Launching image {:?}...", path.to_string());
+    bs.start_image(image_handle).inspect_err(|err| {
+        error!(
+            "image {:?} returned an error ({})",
+            path.to_string(),
+            err.status()
+        )
+    })
 }
 
 fn get_image_path_for<'a>(
@@ -90,11 +211,7 @@ fn get_image_path_for<'a>(
                 }
                 builder = builder.push(&node).unwrap();
             }
-            builder = builder
-                .push(&build::media::FilePath {
-                    path_name: &path_name,
-                })
-                .unwrap();
+            builder = builder.push(&build::media::FilePath { path_name }).unwrap();
 
             Ok(builder.finalize().unwrap())
         }
@@ -0,0 +1,223 @@
+//! Discovery and selection of the volumes files are read from.
+//!
+//! Volumes are selected, in priority order, with the following selectors:
+//!
+//!  - `self`: the volume fdtshim was loaded from (default),
+//!  - `esp`: any EFI System Partition,
+//!  - `xbootldr`: any Extended Boot Loader Partition (Boot Loader Specification),
+//!  - `type:GUID`: any partition with the given GPT partition type GUID,
+//!  - `partlabel:NAME`: any partition with the given GPT partition name,
+//!  - `label:NAME`: any filesystem with the given volume label,
+//!  - `any`: all volumes.
+//!
+
+use crate::global::Global;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::OnceCell;
+use core::fmt;
+use log::warn;
+use uefi::fs::FileSystem;
+use uefi::prelude::*;
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::proto::media::file::File;
+use uefi::proto::media::file::FileSystemVolumeLabel;
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::proto::media::partition::GptPartitionType;
+use uefi::proto::media::partition::PartitionInfo;
+use uefi::table::boot::OpenProtocolAttributes;
+use uefi::table::boot::OpenProtocolParams;
+use uefi::table::boot::ScopedProtocol;
+use uefi::table::boot::SearchType;
+use uefi::Identify;
+use uefi::{guid, Guid};
+
+/// Extended Boot Loader Partition, from the Boot Loader Specification.
+const XBOOTLDR_PARTITION_TYPE_GUID: Guid = guid!("bc13c2ff-59e6-4262-a352-b275fd102c72");
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VolumeSelector {
+    Image,
+    Any,
+    PartitionType(Guid),
+    PartitionLabel(String),
+    Label(String),
+}
+
+impl VolumeSelector {
+    pub fn parse(selector: &str) -> Option<Self> {
+        match selector.split_once(':') {
+            Some(("type", guid)) => guid.parse().ok().map(Self::PartitionType),
+            Some(("partlabel", name)) => Some(Self::PartitionLabel(name.to_string())),
+            Some(("label", name)) => Some(Self::Label(name.to_string())),
+            Some(_) => None,
+            None => match selector {
+                "self" => Some(Self::Image),
+                "any" => Some(Self::Any),
+                "esp" => Some(Self::PartitionType(
+                    GptPartitionType::EFI_SYSTEM_PARTITION.0,
+                )),
+                "xbootldr" => Some(Self::PartitionType(XBOOTLDR_PARTITION_TYPE_GUID)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn matches(&self, bs: &BootServices, volume: &Volume) -> bool {
+        match self {
+            Self::Image => volume.is_image,
+            Self::Any => true,
+            Self::PartitionType(guid) => volume.partition_type(bs).as_ref() == Some(guid),
+            Self::PartitionLabel(name) => volume.partition_label(bs) == Some(name.as_str()),
+            Self::Label(name) => volume.label(bs) == Some(name.as_str()),
+        }
+    }
+}
+
+/// A handle providing the `SimpleFileSystem` protocol, and what identifies it.
+///
+/// The partition and the label are only probed when a selector needs them.
+pub struct Volume {
+    pub handle: Handle,
+    /// Whether this is the volume fdtshim was loaded from.
+    pub is_image: bool,
+    /// GPT partition type and name.
+    partition: OnceCell<Option<(Guid, String)>>,
+    label: OnceCell<Option<String>>,
+}
+
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `?` when not probed, `-` when absent.
+        let describe = |value: Option<Option<String>>| match value {
+            Some(Some(value)) => value,
+            Some(None) => "-".to_string(),
+            None => "?".to_string(),
+        };
+        let partition = self.partition.get().map(Option::as_ref);
+        write!(
+            f,
+            "{:?}{} type={} partlabel={:?} label={:?}",
+            self.handle,
+            if self.is_image { " (self)" } else { "" },
+            describe(partition.map(|p| p.map(|(guid, _)| format!("{guid}")))),
+            describe(partition.map(|p| p.map(|(_, name)| name.clone()))),
+            describe(self.label.get().cloned()),
+        )
+    }
+}
+
+impl Volume {
+    fn new(handle: Handle, image_device: Option<Handle>) -> Self {
+        Self {
+            handle,
+            is_image: Some(handle) == image_device,
+            partition: OnceCell::new(),
+            label: OnceCell::new(),
+        }
+    }
+
+    fn partition(&self, bs: &BootServices) -> Option<&(Guid, String)> {
+        self.partition
+            .get_or_init(|| {
+                // NOTE: Not all firmwares implement the `EFI_PARTITION_INFO_PROTOCOL`.
+                let partition_info = open::<PartitionInfo>(bs, self.handle).ok()?;
+                let entry = partition_info.gpt_partition_entry()?;
+                let partition_name = entry.partition_name;
+                let name = partition_name
+                    .iter()
+                    .map(|c| char::from(*c))
+                    .take_while(|c| *c != '\0')
+                    .collect();
+                Some((entry.partition_type_guid.0, name))
+            })
+            .as_ref()
+    }
+
+    pub fn partition_type(&self, bs: &BootServices) -> Option<Guid> {
+        self.partition(bs).map(|(guid, _)| *guid)
+    }
+
+    pub fn partition_label(&self, bs: &BootServices) -> Option<&str> {
+        self.partition(bs).map(|(_, name)| name.as_str())
+    }
+
+    pub fn label(&self, bs: &BootServices) -> Option<&str> {
+        self.label
+            .get_or_init(|| {
+                let mut fs = open::<SimpleFileSystem>(bs, self.handle).ok()?;
+                let mut root = fs.open_volume().ok()?;
+                let label = root.get_boxed_info::<FileSystemVolumeLabel>().ok()?;
+                Some(label.volume_label().to_string())
+            })
+            .as_deref()
+    }
+
+    pub fn file_system<'a>(&self, bs: &'a BootServices) -> uefi::Result<FileSystem<'a>> {
+        open::<SimpleFileSystem>(bs, self.handle).map(FileSystem::new)
+    }
+}
+
+/// Opens a protocol non-exclusively, so as not to disconnect drivers from other volumes.
+fn open<P: uefi::proto::ProtocolPointer + ?Sized>(
+    bs: &BootServices,
+    handle: Handle,
+) -> uefi::Result<ScopedProtocol<'_, P>> {
+    unsafe {
+        bs.open_protocol::<P>(
+            OpenProtocolParams {
+                handle,
+                agent: bs.image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+}
+
+static VOLUMES: Global<OnceCell<Vec<Volume>>> = Global::new(OnceCell::new());
+
+/// Lists all volumes, the one fdtshim was loaded from first.
+///
+/// The volumes are enumerated once, on first use.
+pub fn volumes(bs: &BootServices) -> &'static [Volume] {
+    VOLUMES.get().get_or_init(|| {
+        let image_device = bs
+            .open_protocol_exclusive::<LoadedImage>(bs.image_handle())
+            .ok()
+            .and_then(|loaded_image| loaded_image.device());
+
+        let mut volumes: Vec<Volume> = bs
+            .locate_handle_buffer(SearchType::ByProtocol(&SimpleFileSystem::GUID))
+            .map(|handles| {
+                handles
+                    .iter()
+                    .map(|handle| Volume::new(*handle, image_device))
+                    .collect()
+            })
+            .unwrap_or_default();
+        volumes.sort_by_key(|volume| !volume.is_image);
+
+        volumes
+    })
+}
+
+/// Lists the volumes matching the selectors, in priority order.
+pub fn selected_volumes(bs: &BootServices, selectors: &[VolumeSelector]) -> Vec<&'static Volume> {
+    let mut volumes: Vec<Option<&Volume>> = volumes(bs).iter().map(Some).collect();
+    let mut selected = Vec::new();
+    for selector in selectors.iter() {
+        for volume in volumes.iter_mut() {
+            if volume.is_some_and(|volume| selector.matches(bs, volume)) {
+                selected.extend(volume.take());
+            }
+        }
+    }
+    if selected.is_empty() {
+        warn!("No volume matches the selectors {:?}", selectors);
+    }
+
+    selected
+}